@@ -1,11 +1,21 @@
-use clap::{Arg, ArgAction, Command};
+use crate::config::{ConfigFormat, ExitPolicy};
+use crate::error::LoopError;
+use crate::order::SortOrder;
+use crate::output::OutputMode;
+use crate::source::DirectorySource;
+use clap::{value_parser, Arg, ArgAction, ArgMatches, Command};
+use std::sync::Arc;
 
 /// Represents the command-line options for the loop command.
 ///
 /// This struct holds all the possible options that can be passed to the loop command,
 /// including the command to execute, directories to include or exclude, and patterns
 /// for filtering directories.
-#[derive(Debug, Clone)]
+///
+/// Library users should construct it with [`LoopOptions::builder`], as fields may be
+/// added in any release.
+#[derive(Debug, Clone, Default)]
+#[non_exhaustive]
 pub struct LoopOptions {
     pub command: Vec<String>,
     pub cwd: Option<String>,
@@ -16,6 +26,324 @@ pub struct LoopOptions {
     pub include_pattern: Option<String>,
     pub exclude_pattern: Option<String>,
     pub init: bool,
+    pub format: Option<ConfigFormat>,
+    pub force: bool,
+    pub group: Option<Vec<String>>,
+    pub shell: Option<String>,
+    pub no_shell: bool,
+    pub no_rc: bool,
+    pub rc: bool,
+    pub dotenv: bool,
+    pub script: Option<String>,
+    pub steps: Option<Vec<String>>,
+    pub pipeline: Option<String>,
+    pub exit_status: Option<ExitPolicy>,
+    pub sort: Option<SortOrder>,
+    pub reverse: bool,
+    pub allow_invalid_config: bool,
+    pub config_command: Option<ConfigCommand>,
+    /// Where directories are found. The immediate children of the root if `None`.
+    pub source: Option<Arc<dyn DirectorySource>>,
+    /// What happens to the output of the commands run.
+    pub output: OutputMode,
+}
+
+impl LoopOptions {
+    /// Returns a builder for options, starting from the defaults of the `loop` command.
+    pub fn builder() -> LoopOptionsBuilder {
+        LoopOptionsBuilder::default()
+    }
+}
+
+/// Builds [`LoopOptions`], checking at `build` the same combinations the command line
+/// rejects.
+///
+/// ```
+/// use loop_lib::args::LoopOptions;
+///
+/// let options = LoopOptions::builder()
+///     .command(["git", "status"])
+///     .cwd("~/src")
+///     .exclude(["vendor"])
+///     .build()
+///     .unwrap();
+/// assert_eq!(options.command, vec!["git", "status"]);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct LoopOptionsBuilder {
+    options: LoopOptions,
+}
+
+fn strings(values: impl IntoIterator<Item = impl Into<String>>) -> Vec<String> {
+    values.into_iter().map(Into::into).collect()
+}
+
+impl LoopOptionsBuilder {
+    /// The command to run in each directory, as a program followed by its arguments.
+    pub fn command(mut self, command: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.options.command = strings(command);
+        self
+    }
+
+    /// The directory to loop over instead of the current directory.
+    pub fn cwd(mut self, cwd: impl Into<String>) -> Self {
+        self.options.cwd = Some(cwd.into());
+        self
+    }
+
+    /// Additional directories to run in.
+    pub fn include(mut self, dirs: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.options.include = Some(strings(dirs));
+        self
+    }
+
+    /// Directories to skip.
+    pub fn exclude(mut self, dirs: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.options.exclude = Some(strings(dirs));
+        self
+    }
+
+    /// Runs only in these directories.
+    pub fn include_only(mut self, dirs: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.options.include_only = Some(strings(dirs));
+        self
+    }
+
+    /// Runs in every directory except these.
+    pub fn exclude_only(mut self, dirs: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.options.exclude_only = Some(strings(dirs));
+        self
+    }
+
+    /// Runs only in directories matching a regular expression.
+    pub fn include_pattern(mut self, pattern: impl Into<String>) -> Self {
+        self.options.include_pattern = Some(pattern.into());
+        self
+    }
+
+    /// Skips directories matching a regular expression.
+    pub fn exclude_pattern(mut self, pattern: impl Into<String>) -> Self {
+        self.options.exclude_pattern = Some(pattern.into());
+        self
+    }
+
+    /// Creates a configuration file instead of running a command.
+    pub fn init(mut self, init: bool) -> Self {
+        self.options.init = init;
+        self
+    }
+
+    /// The format of the configuration file created by `init`.
+    pub fn format(mut self, format: ConfigFormat) -> Self {
+        self.options.format = Some(format);
+        self
+    }
+
+    /// Lets `init` overwrite an existing configuration file.
+    pub fn force(mut self, force: bool) -> Self {
+        self.options.force = force;
+        self
+    }
+
+    /// Runs only in the directories of these configured groups.
+    pub fn group(mut self, groups: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.options.group = Some(strings(groups));
+        self
+    }
+
+    /// The shell used to run the command.
+    pub fn shell(mut self, shell: impl Into<String>) -> Self {
+        self.options.shell = Some(shell.into());
+        self
+    }
+
+    /// Runs the command directly instead of through a shell.
+    pub fn no_shell(mut self, no_shell: bool) -> Self {
+        self.options.no_shell = no_shell;
+        self
+    }
+
+    /// Starts the shell without reading its startup files.
+    pub fn no_rc(mut self, no_rc: bool) -> Self {
+        self.options.no_rc = no_rc;
+        self
+    }
+
+    /// Reads the shell's startup files even if the configuration disables them.
+    pub fn rc(mut self, rc: bool) -> Self {
+        self.options.rc = rc;
+        self
+    }
+
+    /// Loads each directory's `.env` file.
+    pub fn dotenv(mut self, dotenv: bool) -> Self {
+        self.options.dotenv = dotenv;
+        self
+    }
+
+    /// A script to run in each directory, with the command as its arguments.
+    pub fn script(mut self, script: impl Into<String>) -> Self {
+        self.options.script = Some(script.into());
+        self
+    }
+
+    /// Commands to run one after another in each directory.
+    pub fn steps(mut self, steps: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.options.steps = Some(strings(steps));
+        self
+    }
+
+    /// A configured pipeline to run in each directory.
+    pub fn pipeline(mut self, pipeline: impl Into<String>) -> Self {
+        self.options.pipeline = Some(pipeline.into());
+        self
+    }
+
+    /// How the exit status of the run is derived from the commands' statuses.
+    pub fn exit_status(mut self, policy: ExitPolicy) -> Self {
+        self.options.exit_status = Some(policy);
+        self
+    }
+
+    /// The order directories are run in.
+    pub fn sort(mut self, order: SortOrder) -> Self {
+        self.options.sort = Some(order);
+        self
+    }
+
+    /// Runs directories in the reverse of the sort order.
+    pub fn reverse(mut self, reverse: bool) -> Self {
+        self.options.reverse = reverse;
+        self
+    }
+
+    /// Runs with the default configuration if the configuration file is invalid.
+    pub fn allow_invalid_config(mut self, allow: bool) -> Self {
+        self.options.allow_invalid_config = allow;
+        self
+    }
+
+    /// A `loop config` action to perform instead of running a command.
+    pub fn config_command(mut self, config_command: ConfigCommand) -> Self {
+        self.options.config_command = Some(config_command);
+        self
+    }
+
+    /// Where to find the directories to run in, instead of the root's children.
+    pub fn source(mut self, source: impl DirectorySource + 'static) -> Self {
+        self.options.source = Some(Arc::new(source));
+        self
+    }
+
+    /// Whether the commands' output is printed, captured into the report, both, or
+    /// thrown away.
+    pub fn output(mut self, output: OutputMode) -> Self {
+        self.options.output = output;
+        self
+    }
+
+    /// Checks the options and returns them.
+    ///
+    /// Fails with [`LoopError::InvalidOptions`] for the combinations the command line
+    /// rejects, such as a missing command or both `shell` and `no_shell`.
+    pub fn build(self) -> Result<LoopOptions, LoopError> {
+        let options = self.options;
+        if options.command.is_empty()
+            && !options.init
+            && options.script.is_none()
+            && options.steps.is_none()
+            && options.pipeline.is_none()
+            && options.config_command.is_none()
+        {
+            return Err(LoopError::InvalidOptions(
+                "a command, script, steps or pipeline is required".to_string(),
+            ));
+        }
+
+        let conflicts = [
+            (
+                "command",
+                !options.command.is_empty(),
+                "steps",
+                options.steps.is_some(),
+            ),
+            (
+                "command",
+                !options.command.is_empty(),
+                "pipeline",
+                options.pipeline.is_some(),
+            ),
+            (
+                "script",
+                options.script.is_some(),
+                "steps",
+                options.steps.is_some(),
+            ),
+            (
+                "script",
+                options.script.is_some(),
+                "pipeline",
+                options.pipeline.is_some(),
+            ),
+            (
+                "steps",
+                options.steps.is_some(),
+                "pipeline",
+                options.pipeline.is_some(),
+            ),
+            (
+                "shell",
+                options.shell.is_some(),
+                "no_shell",
+                options.no_shell,
+            ),
+            (
+                "no_shell",
+                options.no_shell,
+                "steps",
+                options.steps.is_some(),
+            ),
+            (
+                "no_shell",
+                options.no_shell,
+                "pipeline",
+                options.pipeline.is_some(),
+            ),
+            ("rc", options.rc, "no_rc", options.no_rc),
+        ];
+        if let Some((first, _, second, _)) = conflicts.iter().find(|(_, a, _, b)| *a && *b) {
+            return Err(LoopError::InvalidOptions(format!(
+                "{} cannot be used with {}",
+                first, second
+            )));
+        }
+
+        for (name, set) in [
+            ("format", options.format.is_some()),
+            ("force", options.force),
+        ] {
+            if set && !options.init {
+                return Err(LoopError::InvalidOptions(format!("{} requires init", name)));
+            }
+        }
+
+        Ok(options)
+    }
+}
+
+/// The actions available under the `loop config` subcommand.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConfigCommand {
+    /// Checks the configuration file and exits non-zero if it is invalid.
+    Validate,
+    /// Prints the effective configuration and where each value comes from.
+    Show,
+    /// Prints the value of a single configuration key.
+    Get { key: String },
+    /// Sets a configuration key in the configuration file.
+    Set { key: String, values: Vec<String> },
+    /// Prints the environment variables loop sets for a directory.
+    Env { dir: String },
 }
 
 /// Parses command-line arguments and returns a LoopOptions struct.
@@ -23,26 +351,65 @@ pub struct LoopOptions {
 /// This function uses the clap library to define and parse command-line arguments,
 /// converting them into a LoopOptions struct for easy use in the rest of the program.
 pub fn parse_args() -> LoopOptions {
-    let matches = Command::new("loop")
+    options_from_matches(&command().get_matches())
+}
+
+/// Builds the clap command definition for loop.
+fn command() -> Command {
+    Command::new("loop")
         .about("Loop through directories and execute a command")
         .version(env!("CARGO_PKG_VERSION"))
         .author("Matt")
+        .subcommand_negates_reqs(true)
+        .args_conflicts_with_subcommands(true)
+        .subcommand(
+            Command::new("config")
+                .about("Inspect the loop configuration")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("validate")
+                        .about("Check the configuration file and report any errors"),
+                )
+                .subcommand(
+                    Command::new("show")
+                        .about("Show the effective configuration and the source of each value"),
+                )
+                .subcommand(
+                    Command::new("get")
+                        .about("Print the value of a configuration key")
+                        .arg(Arg::new("key").required(true)),
+                )
+                .subcommand(
+                    Command::new("set")
+                        .about("Set a configuration key in the configuration file")
+                        .arg(Arg::new("key").required(true))
+                        .arg(Arg::new("value").required(true).num_args(1..)),
+                )
+                .subcommand(
+                    Command::new("env")
+                        .about("Print the environment variables set for a directory")
+                        .arg(Arg::new("dir").required(true)),
+                ),
+        )
         .arg(
             Arg::new("command")
-                .required_unless_present("init")
+                .required_unless_present_any(["init", "script", "step", "pipeline"])
+                .conflicts_with_all(["step", "pipeline"])
                 .num_args(1..)
-                .help("The command to execute in each directory"),
+                .help("The command to execute in each directory, or the arguments to --script"),
         )
         .arg(
             Arg::new("cwd")
                 .short('C')
                 .long("cwd")
+                .global(true)
                 .help("The current working directory"),
         )
         .arg(
             Arg::new("include")
                 .short('i')
                 .long("include")
+                .global(true)
                 .num_args(1..)
                 .help("Additional directories to include"),
         )
@@ -50,29 +417,34 @@ pub fn parse_args() -> LoopOptions {
             Arg::new("exclude")
                 .short('e')
                 .long("exclude")
+                .global(true)
                 .num_args(1..)
                 .help("Directories to exclude"),
         )
         .arg(
             Arg::new("include_only")
                 .long("include-only")
+                .global(true)
                 .num_args(1..)
                 .help("Only include these directories"),
         )
         .arg(
             Arg::new("exclude_only")
                 .long("exclude-only")
+                .global(true)
                 .num_args(1..)
                 .help("Exclude all directories except these"),
         )
         .arg(
             Arg::new("include_pattern")
                 .long("include-pattern")
+                .global(true)
                 .help("A pattern to include directories"),
         )
         .arg(
             Arg::new("exclude_pattern")
                 .long("exclude-pattern")
+                .global(true)
                 .help("A pattern to exclude directories"),
         )
         .arg(
@@ -81,8 +453,127 @@ pub fn parse_args() -> LoopOptions {
                 .action(ArgAction::SetTrue)
                 .help("Initialize the loop configuration"),
         )
-        .get_matches();
+        .arg(
+            Arg::new("format")
+                .long("format")
+                .requires("init")
+                .value_parser(value_parser!(ConfigFormat))
+                .help("The configuration file format to generate with --init"),
+        )
+        .arg(
+            Arg::new("force")
+                .long("force")
+                .requires("init")
+                .action(ArgAction::SetTrue)
+                .help("Overwrite an existing configuration file with --init"),
+        )
+        .arg(
+            Arg::new("group")
+                .short('g')
+                .long("group")
+                .global(true)
+                .num_args(1..)
+                .help("Only include directories in these configured groups"),
+        )
+        .arg(
+            Arg::new("script")
+                .long("script")
+                .value_name("SCRIPT")
+                .global(true)
+                .help(
+                "Run a script file, or a script named in the configuration, in each directory",
+            ),
+        )
+        .arg(
+            Arg::new("step")
+                .long("step")
+                .global(true)
+                .value_name("COMMAND")
+                .action(ArgAction::Append)
+                .allow_hyphen_values(true)
+                .conflicts_with_all(["script", "pipeline"])
+                .help("A step to run in each directory; repeat for a pipeline, prefix with - to continue on failure"),
+        )
+        .arg(
+            Arg::new("pipeline")
+                .long("pipeline")
+                .global(true)
+                .value_name("NAME")
+                .conflicts_with("script")
+                .help("Run a pipeline from the configuration in each directory"),
+        )
+        .arg(
+            Arg::new("exit_status")
+                .long("exit-status")
+                .global(true)
+                .value_name("POLICY")
+                .value_parser(value_parser!(ExitPolicy))
+                .help("How to combine the exit codes of the directories into loop's exit code"),
+        )
+        .arg(
+            Arg::new("sort")
+                .long("sort")
+                .global(true)
+                .value_name("ORDER")
+                .value_parser(value_parser!(SortOrder))
+                .help("The order to run directories in"),
+        )
+        .arg(
+            Arg::new("reverse")
+                .long("reverse")
+                .global(true)
+                .action(ArgAction::SetTrue)
+                .help("Run directories in the reverse order"),
+        )
+        .arg(
+            Arg::new("shell")
+                .long("shell")
+                .global(true)
+                .conflicts_with("no_shell")
+                .help("The shell to run the command with (defaults to $SHELL)"),
+        )
+        .arg(
+            Arg::new("no_shell")
+                .long("no-shell")
+                .global(true)
+                .visible_alias("exec")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["step", "pipeline"])
+                .help("Run the command directly, without a shell"),
+        )
+        .arg(
+            Arg::new("no_rc")
+                .long("no-rc")
+                .global(true)
+                .action(ArgAction::SetTrue)
+                .help("Do not source the shell's rc file before each command"),
+        )
+        .arg(
+            Arg::new("rc")
+                .long("rc")
+                .global(true)
+                .conflicts_with("no_rc")
+                .action(ArgAction::SetTrue)
+                .help("Source the shell's rc file even if the configuration disables it"),
+        )
+        .arg(
+            Arg::new("dotenv")
+                .long("dotenv")
+                .global(true)
+                .action(ArgAction::SetTrue)
+                .help("Load a .env file from each directory into the command's environment"),
+        )
+        .arg(
+            Arg::new("allow_invalid_config")
+                .long("allow-invalid-config")
+                .global(true)
+                .action(ArgAction::SetTrue)
+                .help("Warn about an invalid configuration file instead of exiting"),
+        )
+}
 
+/// Converts parsed clap matches into a LoopOptions struct.
+fn options_from_matches(matches: &ArgMatches) -> LoopOptions {
     LoopOptions {
         command: matches
             .get_many::<String>("command")
@@ -104,6 +595,48 @@ pub fn parse_args() -> LoopOptions {
         include_pattern: matches.get_one::<String>("include_pattern").cloned(),
         exclude_pattern: matches.get_one::<String>("exclude_pattern").cloned(),
         init: matches.get_flag("init"),
+        format: matches.get_one::<ConfigFormat>("format").copied(),
+        force: matches.get_flag("force"),
+        group: matches
+            .get_many::<String>("group")
+            .map(|v| v.cloned().collect()),
+        shell: matches.get_one::<String>("shell").cloned(),
+        no_shell: matches.get_flag("no_shell"),
+        no_rc: matches.get_flag("no_rc"),
+        rc: matches.get_flag("rc"),
+        dotenv: matches.get_flag("dotenv"),
+        script: matches.get_one::<String>("script").cloned(),
+        steps: matches
+            .get_many::<String>("step")
+            .map(|v| v.cloned().collect()),
+        pipeline: matches.get_one::<String>("pipeline").cloned(),
+        exit_status: matches.get_one::<ExitPolicy>("exit_status").copied(),
+        sort: matches.get_one::<SortOrder>("sort").copied(),
+        reverse: matches.get_flag("reverse"),
+        allow_invalid_config: matches.get_flag("allow_invalid_config"),
+        config_command: matches
+            .subcommand_matches("config")
+            .and_then(|config| config.subcommand())
+            .map(|(name, matches)| match name {
+                "validate" => ConfigCommand::Validate,
+                "show" => ConfigCommand::Show,
+                "get" => ConfigCommand::Get {
+                    key: matches.get_one::<String>("key").cloned().unwrap(),
+                },
+                "set" => ConfigCommand::Set {
+                    key: matches.get_one::<String>("key").cloned().unwrap(),
+                    values: matches
+                        .get_many::<String>("value")
+                        .map(|v| v.cloned().collect())
+                        .unwrap_or_default(),
+                },
+                "env" => ConfigCommand::Env {
+                    dir: matches.get_one::<String>("dir").cloned().unwrap(),
+                },
+                _ => unreachable!("unknown config subcommand {}", name),
+            }),
+        source: None,
+        output: OutputMode::Inherit,
     }
 }
 
@@ -125,48 +658,197 @@ mod tests {
         let args = parse_args_from(&["loop", "--init"]);
         assert!(args.init);
         assert!(args.command.is_empty());
+        assert!(args.format.is_none());
+    }
+
+    #[test]
+    fn test_init_format() {
+        let args = parse_args_from(&["loop", "--init", "--format", "toml"]);
+        assert!(args.init);
+        assert_eq!(args.format, Some(ConfigFormat::Toml));
+    }
+
+    #[test]
+    fn test_init_force_requires_init() {
+        let args = parse_args_from(&["loop", "--init", "--force"]);
+        assert!(args.force);
+        assert!(command()
+            .try_get_matches_from(["loop", "ls", "--force"])
+            .is_err());
+    }
+
+    #[test]
+    fn test_group_flag() {
+        let args = parse_args_from(&["loop", "-g", "rust", "node", "--", "cargo", "build"]);
+        assert_eq!(
+            args.group,
+            Some(vec!["rust".to_string(), "node".to_string()])
+        );
+        assert_eq!(args.command, vec!["cargo", "build"]);
+    }
+
+    #[test]
+    fn test_shell_flags() {
+        let args = parse_args_from(&["loop", "--shell", "/bin/bash", "ls"]);
+        assert_eq!(args.shell, Some("/bin/bash".to_string()));
+        assert!(!args.no_shell);
+
+        let args = parse_args_from(&["loop", "--exec", "--", "ls", "-la"]);
+        assert!(args.no_shell);
+        assert!(command()
+            .try_get_matches_from(["loop", "--shell", "bash", "--no-shell", "ls"])
+            .is_err());
+        assert!(command()
+            .try_get_matches_from(["loop", "--no-shell", "--step", "echo hi"])
+            .is_err());
+        assert!(command()
+            .try_get_matches_from(["loop", "--no-shell", "--pipeline", "ci"])
+            .is_err());
+    }
+
+    #[test]
+    fn test_script_flag() {
+        let args = parse_args_from(&["loop", "--script", "./update.sh"]);
+        assert_eq!(args.script, Some("./update.sh".to_string()));
+        assert!(args.command.is_empty());
+
+        let args = parse_args_from(&["loop", "--script", "release", "--", "--dry-run"]);
+        assert_eq!(args.command, vec!["--dry-run"]);
+    }
+
+    #[test]
+    fn test_step_flags() {
+        let args = parse_args_from(&[
+            "loop",
+            "--step",
+            "git pull",
+            "--step",
+            "-npm install",
+            "--step",
+            "npm test",
+        ]);
+        assert_eq!(
+            args.steps,
+            Some(vec![
+                "git pull".to_string(),
+                "-npm install".to_string(),
+                "npm test".to_string()
+            ])
+        );
+        assert!(args.command.is_empty());
+
+        let args = parse_args_from(&["loop", "--pipeline", "update"]);
+        assert_eq!(args.pipeline, Some("update".to_string()));
+        assert!(command()
+            .try_get_matches_from(["loop", "--step", "ls", "--pipeline", "update"])
+            .is_err());
+        assert!(command()
+            .try_get_matches_from(["loop", "--step", "ls", "pwd"])
+            .is_err());
+    }
+
+    #[test]
+    fn test_exit_status_flag() {
+        let args = parse_args_from(&["loop", "--exit-status", "max", "ls"]);
+        assert_eq!(args.exit_status, Some(ExitPolicy::Max));
+        assert!(command()
+            .try_get_matches_from(["loop", "--exit-status", "sum", "ls"])
+            .is_err());
+    }
+
+    #[test]
+    fn test_config_validate_subcommand() {
+        let args = parse_args_from(&["loop", "config", "validate", "-C", "/tmp"]);
+        assert_eq!(args.config_command, Some(ConfigCommand::Validate));
+        assert_eq!(args.cwd, Some("/tmp".to_string()));
+        assert!(args.command.is_empty());
+    }
+
+    #[test]
+    fn test_config_show_with_flags() {
+        let args = parse_args_from(&["loop", "config", "show", "--exclude", "target"]);
+        assert_eq!(args.config_command, Some(ConfigCommand::Show));
+        assert_eq!(args.exclude, Some(vec!["target".to_string()]));
+    }
+
+    #[test]
+    fn test_config_set_subcommand() {
+        let args = parse_args_from(&["loop", "config", "set", "ignore", ".git", "target"]);
+        assert_eq!(
+            args.config_command,
+            Some(ConfigCommand::Set {
+                key: "ignore".to_string(),
+                values: vec![".git".to_string(), "target".to_string()],
+            })
+        );
+    }
+
+    #[test]
+    fn test_config_env_subcommand() {
+        let args = parse_args_from(&["loop", "config", "env", "api", "--dotenv"]);
+        assert_eq!(
+            args.config_command,
+            Some(ConfigCommand::Env {
+                dir: "api".to_string()
+            })
+        );
+        assert!(args.dotenv);
+    }
+
+    #[test]
+    fn test_command_is_not_a_subcommand() {
+        let args = parse_args_from(&["loop", "git", "config", "user.name"]);
+        assert!(args.config_command.is_none());
+        assert_eq!(args.command, vec!["git", "config", "user.name"]);
+    }
+
+    #[test]
+    fn test_builder_defaults() {
+        let options = LoopOptions::builder().command(["ls"]).build().unwrap();
+        assert_eq!(options.command, vec!["ls"]);
+        assert!(options.cwd.is_none());
+        assert!(!options.no_shell);
+        assert!(options.exit_status.is_none());
+    }
+
+    #[test]
+    fn test_builder_validation() {
+        let invalid = [
+            LoopOptions::builder(),
+            LoopOptions::builder().command(["ls"]).steps(["make"]),
+            LoopOptions::builder().script("update.sh").pipeline("ci"),
+            LoopOptions::builder()
+                .command(["ls"])
+                .shell("zsh")
+                .no_shell(true),
+            LoopOptions::builder().steps(["echo hi"]).no_shell(true),
+            LoopOptions::builder().pipeline("ci").no_shell(true),
+            LoopOptions::builder().command(["ls"]).rc(true).no_rc(true),
+            LoopOptions::builder().command(["ls"]).force(true),
+        ];
+        for builder in invalid {
+            let err = builder.build().unwrap_err();
+            assert!(matches!(err, LoopError::InvalidOptions(_)), "{}", err);
+            assert_eq!(err.exit_code(), exitcode::USAGE);
+        }
+
+        assert!(LoopOptions::builder()
+            .init(true)
+            .force(true)
+            .build()
+            .is_ok());
+        assert!(LoopOptions::builder()
+            .steps(["make", "-make test"])
+            .build()
+            .is_ok());
+        assert!(LoopOptions::builder()
+            .config_command(ConfigCommand::Show)
+            .build()
+            .is_ok());
     }
 
     // Helper function for testing
     fn parse_args_from(args: &[&str]) -> LoopOptions {
-        let matches = Command::new("loop")
-            .arg(
-                Arg::new("command")
-                    .required_unless_present("init")
-                    .num_args(1..),
-            )
-            .arg(Arg::new("cwd").short('C').long("cwd"))
-            .arg(Arg::new("include").short('i').long("include").num_args(1..))
-            .arg(Arg::new("exclude").short('e').long("exclude").num_args(1..))
-            .arg(Arg::new("include_only").long("include-only").num_args(1..))
-            .arg(Arg::new("exclude_only").long("exclude-only").num_args(1..))
-            .arg(Arg::new("include_pattern").long("include-pattern"))
-            .arg(Arg::new("exclude_pattern").long("exclude-pattern"))
-            .arg(Arg::new("init").long("init").action(ArgAction::SetTrue))
-            .try_get_matches_from(args)
-            .unwrap();
-
-        LoopOptions {
-            command: matches
-                .get_many::<String>("command")
-                .map(|v| v.cloned().collect())
-                .unwrap_or_default(),
-            cwd: matches.get_one::<String>("cwd").cloned(),
-            include: matches
-                .get_many::<String>("include")
-                .map(|v| v.cloned().collect()),
-            exclude: matches
-                .get_many::<String>("exclude")
-                .map(|v| v.cloned().collect()),
-            include_only: matches
-                .get_many::<String>("include_only")
-                .map(|v| v.cloned().collect()),
-            exclude_only: matches
-                .get_many::<String>("exclude_only")
-                .map(|v| v.cloned().collect()),
-            include_pattern: matches.get_one::<String>("include_pattern").cloned(),
-            exclude_pattern: matches.get_one::<String>("exclude_pattern").cloned(),
-            init: matches.get_flag("init"),
-        }
+        options_from_matches(&command().try_get_matches_from(args).unwrap())
     }
 }
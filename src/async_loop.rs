@@ -0,0 +1,563 @@
+//! An async counterpart of `execute_loop`, built on `tokio::process`.
+//!
+//! Available with the `async` feature. Commands are spawned the same way as by
+//! `ShellExecutor`, but without blocking a thread while they run. Several directories
+//! can run at once, the run can be cancelled with a token, and the lines commands
+//! print can be streamed over a channel.
+
+use crate::args::LoopOptions;
+use crate::config::PipelineStep;
+use crate::error::LoopError;
+use crate::executor::{self, ExecSettings, Iteration, Outcome};
+use crate::observer::{NoopObserver, Stream};
+use crate::output::{LineBuffer, OutputMode};
+use crate::report::{CapturedOutput, DirOutcome, RunReport, SkipReason, Status, StepOutcome};
+use crate::signals;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{ExitStatus, Stdio};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::process::{Child, Command};
+use tokio::sync::{mpsc, Semaphore};
+use tokio::task::JoinSet;
+
+pub use tokio_util::sync::CancellationToken;
+
+/// A line printed by a command, sent while the command runs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutputLine {
+    /// The directory the command runs in.
+    pub path: PathBuf,
+    pub stream: Stream,
+    /// The line, without its line ending. Invalid UTF-8 is replaced.
+    pub line: String,
+}
+
+/// How an async run is carried out.
+#[derive(Debug, Clone)]
+pub struct AsyncSettings {
+    /// The number of directories run at the same time. Defaults to 1, running the
+    /// directories one after another like `execute_loop`.
+    pub concurrency: usize,
+    /// Cancels the run. Running commands are sent SIGTERM and no further directories
+    /// are started.
+    pub cancel: CancellationToken,
+    /// How long a cancelled command is given to exit after SIGTERM before it is killed.
+    /// Defaults to 10 seconds.
+    pub kill_after: Duration,
+    /// Receives every line commands print. Without it, commands inherit loop's output
+    /// unless the options' output mode captures or discards it.
+    pub output: Option<mpsc::UnboundedSender<OutputLine>>,
+}
+
+impl Default for AsyncSettings {
+    fn default() -> AsyncSettings {
+        AsyncSettings {
+            concurrency: 1,
+            cancel: CancellationToken::new(),
+            kill_after: Duration::from_secs(10),
+            output: None,
+        }
+    }
+}
+
+/// Executes the loop command with the given options without blocking the calling
+/// thread.
+///
+/// Directories are discovered and filtered as in `execute_loop`, and the returned report
+/// lists them in the same order, whatever order they finish in. Directories not started
+/// because the run was cancelled are reported as skipped with `SkipReason::Cancelled`.
+/// Signals are not handled; cancel the token instead.
+pub async fn execute_loop_async(
+    options: LoopOptions,
+    async_settings: AsyncSettings,
+) -> Result<RunReport, LoopError> {
+    let started = Instant::now();
+    let (options, plan) = tokio::task::spawn_blocking(move || {
+        let plan = crate::plan(&options, &NoopObserver);
+        (options, plan)
+    })
+    .await
+    .unwrap_or_else(|err| std::panic::resume_unwind(err.into_panic()));
+    let crate::Plan {
+        settings,
+        steps,
+        targets,
+        exit_policy,
+    } = plan?;
+
+    let AsyncSettings {
+        concurrency,
+        cancel,
+        kill_after,
+        output,
+    } = async_settings;
+    let settings = Arc::new(settings);
+    let steps = steps.map(Arc::new);
+    let command: Arc<[String]> = options.command.into();
+    let semaphore = Arc::new(Semaphore::new(concurrency.max(1)));
+
+    let total = targets
+        .iter()
+        .filter(|(_, reason)| reason.is_none())
+        .count();
+    let mut directories: Vec<Option<DirOutcome>> = Vec::with_capacity(targets.len());
+    let mut tasks = JoinSet::new();
+    let mut index = 0;
+
+    for (position, (dir, reason)) in targets.into_iter().enumerate() {
+        if let Some(reason) = reason {
+            directories.push(Some(DirOutcome::skipped(dir, reason)));
+            continue;
+        }
+        let permit = tokio::select! {
+            biased;
+            _ = cancel.cancelled() => None,
+            permit = semaphore.clone().acquire_owned() => permit.ok(),
+        };
+        let Some(permit) = permit else {
+            directories.push(Some(DirOutcome::skipped(dir, SkipReason::Cancelled)));
+            continue;
+        };
+        directories.push(None);
+        index += 1;
+
+        let run = DirectoryRun {
+            dir,
+            iteration: Iteration { index, total },
+            settings: settings.clone(),
+            cancel: cancel.clone(),
+            kill_after,
+            output: output.clone(),
+        };
+        let command = command.clone();
+        let steps = steps.clone();
+        tasks.spawn(async move {
+            let _permit = permit;
+            let outcome = match steps {
+                Some(steps) => run.pipeline(&steps).await,
+                None => run.command(&command).await,
+            };
+            (position, outcome)
+        });
+    }
+
+    while let Some(result) = tasks.join_next().await {
+        let (position, outcome) =
+            result.unwrap_or_else(|err| std::panic::resume_unwind(err.into_panic()));
+        directories[position] = Some(outcome);
+    }
+
+    Ok(RunReport {
+        run_id: settings.run_id.clone(),
+        directories: directories.into_iter().flatten().collect(),
+        interrupted: None,
+        exit_policy,
+        duration: started.elapsed(),
+    })
+}
+
+/// A directory being run by an async loop.
+struct DirectoryRun {
+    dir: PathBuf,
+    iteration: Iteration,
+    settings: Arc<ExecSettings>,
+    cancel: CancellationToken,
+    kill_after: Duration,
+    output: Option<mpsc::UnboundedSender<OutputLine>>,
+}
+
+impl DirectoryRun {
+    async fn command(&self, command: &[String]) -> DirOutcome {
+        let started = Instant::now();
+        let captured = self.captured();
+        let result = self.run(command, captured.as_ref()).await;
+        let mut outcome = DirOutcome::finished(self.dir.clone(), result, started.elapsed());
+        outcome.output = captured.map(|captured| captured.into_inner().unwrap());
+        outcome
+    }
+
+    /// Runs the steps of a pipeline as `execute_pipeline_in_directory` does. Once the
+    /// run is cancelled, the remaining steps are skipped.
+    async fn pipeline(&self, steps: &[PipelineStep]) -> DirOutcome {
+        let started = Instant::now();
+        let mut results = Vec::with_capacity(steps.len());
+        let mut result = Ok(Outcome::Exited(0));
+        let captured = self.captured();
+        let running =
+            |result: &Result<Outcome, LoopError>| result.as_ref().is_ok_and(|o| o.success());
+
+        for step in steps {
+            if running(&result) && self.cancel.is_cancelled() {
+                result = Ok(Outcome::Signaled(signals::SIGTERM));
+            }
+            if !running(&result) {
+                results.push(StepOutcome {
+                    label: step.label().to_string(),
+                    status: Status::Skipped,
+                    outcome: None,
+                    duration: Duration::ZERO,
+                    continue_on_error: step.continue_on_error,
+                });
+                continue;
+            }
+
+            let step_started = Instant::now();
+            let step_result = self
+                .run(std::slice::from_ref(&step.run), captured.as_ref())
+                .await;
+            let status = match step_result {
+                Ok(outcome) if outcome.success() => Status::Succeeded,
+                _ => Status::Failed,
+            };
+            results.push(StepOutcome {
+                label: step.label().to_string(),
+                status,
+                outcome: step_result.as_ref().ok().copied(),
+                duration: step_started.elapsed(),
+                continue_on_error: step.continue_on_error,
+            });
+            if status == Status::Failed && !step.continue_on_error {
+                result = step_result;
+            }
+        }
+
+        let mut outcome = DirOutcome::finished(self.dir.clone(), result, started.elapsed());
+        outcome.steps = results;
+        outcome.output = captured.map(|captured| captured.into_inner().unwrap());
+        outcome
+    }
+
+    /// An empty buffer for the directory's output, if the output mode captures it.
+    fn captured(&self) -> Option<Mutex<CapturedOutput>> {
+        self.settings
+            .output
+            .limit()
+            .map(|_| Mutex::new(CapturedOutput::default()))
+    }
+
+    /// Spawns a command, streams its output and waits for it, terminating it if the run
+    /// is cancelled. The output is added to `captured`, if given.
+    async fn run(
+        &self,
+        command: &[String],
+        captured: Option<&Mutex<CapturedOutput>>,
+    ) -> Result<Outcome, LoopError> {
+        let process = executor::build_command(&self.dir, command, &self.settings, self.iteration)?;
+        let program = process.get_program().to_string_lossy().into_owned();
+
+        let mut process = Command::from(process);
+        #[cfg(unix)]
+        process.process_group(0);
+        process.kill_on_drop(true);
+        let mode = self.settings.output;
+        if self.output.is_some() || mode.limit().is_some() {
+            process.stdout(Stdio::piped()).stderr(Stdio::piped());
+        } else if mode == OutputMode::Discard {
+            process.stdout(Stdio::null()).stderr(Stdio::null());
+        } else {
+            process.stdout(Stdio::inherit()).stderr(Stdio::inherit());
+        }
+
+        let mut child = process
+            .spawn()
+            .map_err(|source| LoopError::Spawn { program, source })?;
+        let stdout = child.stdout.take();
+        let stderr = child.stderr.take();
+
+        let (status, _, _) = tokio::join!(
+            self.wait(&mut child),
+            self.forward(stdout, Stream::Stdout, captured),
+            self.forward(stderr, Stream::Stderr, captured),
+        );
+        let status = status.map_err(|source| {
+            LoopError::io(
+                format!("failed to wait for command in {}", self.dir.display()),
+                source,
+            )
+        })?;
+        Ok(Outcome::from_status(status))
+    }
+
+    async fn wait(&self, child: &mut Child) -> std::io::Result<ExitStatus> {
+        tokio::select! {
+            status = child.wait() => status,
+            _ = self.cancel.cancelled() => {
+                terminate(child);
+                match tokio::time::timeout(self.kill_after, child.wait()).await {
+                    Ok(status) => status,
+                    Err(_) => {
+                        kill(child);
+                        child.wait().await
+                    }
+                }
+            }
+        }
+    }
+
+    /// Reads a command's output stream, sending each line to the output channel,
+    /// keeping it in `captured` and printing it, as the output mode requires. A line
+    /// longer than the capture limit, or 1 MiB when output is not captured, is sent in
+    /// pieces.
+    async fn forward(
+        &self,
+        reader: Option<impl AsyncRead + Unpin>,
+        stream: Stream,
+        captured: Option<&Mutex<CapturedOutput>>,
+    ) {
+        let Some(mut reader) = reader else {
+            return;
+        };
+        let mode = self.settings.output;
+        let limit = mode.limit().unwrap_or(OutputMode::DEFAULT_LIMIT);
+        let send = |line: &str| {
+            if let Some(ref output) = self.output {
+                let _ = output.send(OutputLine {
+                    path: self.dir.clone(),
+                    stream,
+                    line: line.to_string(),
+                });
+            }
+        };
+        let mut lines = LineBuffer::default();
+        let mut buffer = [0; 8192];
+        loop {
+            let chunk = match reader.read(&mut buffer).await {
+                Ok(0) => break,
+                Ok(n) => &buffer[..n],
+                Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(_) => break,
+            };
+            if let (Some(captured), Some(limit)) = (captured, mode.limit()) {
+                captured.lock().unwrap().push(stream, chunk, limit);
+            }
+            if let OutputMode::Tee { .. } = mode {
+                let _ = match stream {
+                    Stream::Stdout => std::io::stdout().write_all(chunk),
+                    Stream::Stderr => std::io::stderr().write_all(chunk),
+                };
+            }
+            lines.push(chunk, limit, send);
+        }
+        lines.finish(send);
+    }
+}
+
+/// Sends SIGTERM to a command and everything it started, or kills it where process
+/// groups are not available.
+fn terminate(child: &mut Child) {
+    #[cfg(unix)]
+    if let Some(pid) = child.id() {
+        // SAFETY: kill has no memory safety requirements.
+        unsafe { libc::kill(-(pid as libc::pid_t), signals::SIGTERM) };
+        return;
+    }
+    let _ = child.start_kill();
+}
+
+/// Kills a command and everything it started.
+fn kill(child: &mut Child) {
+    #[cfg(unix)]
+    if let Some(pid) = child.id() {
+        // SAFETY: kill has no memory safety requirements.
+        unsafe { libc::kill(-(pid as libc::pid_t), libc::SIGKILL) };
+        return;
+    }
+    let _ = child.start_kill();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::Path;
+    use tempfile::tempdir;
+
+    fn create_dirs(root: &Path, names: &[&str]) {
+        for name in names {
+            fs::create_dir(root.join(name)).unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_streams_output_in_directory_order() {
+        let temp_dir = tempdir().unwrap();
+        create_dirs(temp_dir.path(), &["alpha", "beta"]);
+
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        let options = LoopOptions::builder()
+            .command(["echo {name}; echo oops >&2"])
+            .cwd(temp_dir.path().to_string_lossy())
+            .no_rc(true)
+            .build()
+            .unwrap();
+        let settings = AsyncSettings {
+            output: Some(sender),
+            ..AsyncSettings::default()
+        };
+        let report = execute_loop_async(options, settings).await.unwrap();
+        assert!(report.success());
+
+        let mut lines = Vec::new();
+        while let Ok(line) = receiver.try_recv() {
+            let name = line
+                .path
+                .file_name()
+                .unwrap()
+                .to_string_lossy()
+                .into_owned();
+            lines.push((name, line.stream, line.line));
+        }
+        lines.sort();
+        assert_eq!(
+            lines,
+            vec![
+                ("alpha".to_string(), Stream::Stdout, "alpha".to_string()),
+                ("alpha".to_string(), Stream::Stderr, "oops".to_string()),
+                ("beta".to_string(), Stream::Stdout, "beta".to_string()),
+                ("beta".to_string(), Stream::Stderr, "oops".to_string()),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_runs_directories_concurrently() {
+        let temp_dir = tempdir().unwrap();
+        create_dirs(temp_dir.path(), &["a", "b", "c", "d"]);
+
+        let options = LoopOptions::builder()
+            .command(["sleep 0.5; test {name} != c"])
+            .cwd(temp_dir.path().to_string_lossy())
+            .no_rc(true)
+            .build()
+            .unwrap();
+        let settings = AsyncSettings {
+            concurrency: 4,
+            ..AsyncSettings::default()
+        };
+        let started = Instant::now();
+        let report = execute_loop_async(options, settings).await.unwrap();
+
+        assert!(started.elapsed() < Duration::from_millis(1500));
+        let names: Vec<_> = report
+            .directories
+            .iter()
+            .map(|dir| dir.path.file_name().unwrap().to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(names, vec!["a", "b", "c", "d"]);
+        assert_eq!(report.failed().count(), 1);
+        assert_eq!(report.directories[2].exit_code(), Some(1));
+    }
+
+    #[tokio::test]
+    async fn test_cancellation_stops_the_run() {
+        let temp_dir = tempdir().unwrap();
+        create_dirs(temp_dir.path(), &["a", "b", "c"]);
+
+        let options = LoopOptions::builder()
+            .command(["sleep 10"])
+            .cwd(temp_dir.path().to_string_lossy())
+            .no_rc(true)
+            .build()
+            .unwrap();
+        let settings = AsyncSettings::default();
+        let cancel = settings.cancel.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(300)).await;
+            cancel.cancel();
+        });
+
+        let started = Instant::now();
+        let report = execute_loop_async(options, settings).await.unwrap();
+
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert_eq!(report.directories[0].signal(), Some(signals::SIGTERM));
+        for dir in &report.directories[1..] {
+            assert_eq!(dir.skip_reason, Some(SkipReason::Cancelled));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_long_lines_are_sent_in_pieces() {
+        let temp_dir = tempdir().unwrap();
+        create_dirs(temp_dir.path(), &["a"]);
+
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        let options = LoopOptions::builder()
+            .command(["head -c 100000 /dev/zero | tr '\\0' x"])
+            .cwd(temp_dir.path().to_string_lossy())
+            .no_rc(true)
+            .output(OutputMode::Capture { limit: 1000 })
+            .build()
+            .unwrap();
+        let settings = AsyncSettings {
+            output: Some(sender),
+            ..AsyncSettings::default()
+        };
+        let report = execute_loop_async(options, settings).await.unwrap();
+        assert!(report.success());
+
+        let mut pieces = Vec::new();
+        while let Ok(line) = receiver.try_recv() {
+            pieces.push(line.line.len());
+        }
+        assert!(pieces.len() > 1);
+        assert_eq!(pieces.iter().sum::<usize>(), 100000);
+        let output = report.directories[0].output.as_ref().unwrap();
+        assert_eq!(output.stdout.len(), 1000);
+        assert!(output.truncated);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_cancelled_command_is_killed_after_timeout() {
+        let temp_dir = tempdir().unwrap();
+        create_dirs(temp_dir.path(), &["a"]);
+
+        let options = LoopOptions::builder()
+            .command(["trap '' TERM; sleep 10"])
+            .shell("/bin/sh")
+            .cwd(temp_dir.path().to_string_lossy())
+            .no_rc(true)
+            .build()
+            .unwrap();
+        let settings = AsyncSettings {
+            kill_after: Duration::from_millis(200),
+            ..AsyncSettings::default()
+        };
+        let cancel = settings.cancel.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(300)).await;
+            cancel.cancel();
+        });
+
+        let started = Instant::now();
+        let report = execute_loop_async(options, settings).await.unwrap();
+
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert_eq!(report.directories[0].signal(), Some(libc::SIGKILL));
+    }
+
+    #[tokio::test]
+    async fn test_captures_output_into_report() {
+        let temp_dir = tempdir().unwrap();
+        create_dirs(temp_dir.path(), &["alpha"]);
+
+        let options = LoopOptions::builder()
+            .steps(["echo {name}", "echo done >&2"])
+            .cwd(temp_dir.path().to_string_lossy())
+            .no_rc(true)
+            .output(OutputMode::capture())
+            .build()
+            .unwrap();
+        let report = execute_loop_async(options, AsyncSettings::default())
+            .await
+            .unwrap();
+
+        let output = report.directories[0].output.as_ref().unwrap();
+        assert_eq!(output.stdout, b"alpha\n");
+        assert_eq!(output.stderr, b"done\n");
+    }
+}
@@ -1,51 +1,1306 @@
+use crate::error::LoopError;
 use serde::{Deserialize, Serialize};
 use serde_json;
+use std::collections::BTreeMap;
+use std::fmt;
 use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// The configuration file names recognised by loop, in order of precedence.
+///
+/// The first file found in a directory wins; the others are ignored.
+pub const CONFIG_FILE_NAMES: &[&str] = &[
+    ".looprc",
+    ".looprc.json",
+    ".looprc.toml",
+    ".looprc.yaml",
+    ".looprc.yml",
+    "loop.toml",
+];
 
 /// Represents the configuration for the loop command.
 ///
 /// This struct holds the configuration options that can be set in the .looprc file,
 /// such as directories to ignore.
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
 pub struct LoopConfig {
+    #[serde(default)]
     pub ignore: Vec<String>,
+    /// Named sets of directories that can be selected with `--group`.
+    #[serde(default)]
+    pub groups: BTreeMap<String, Vec<String>>,
+    /// Shorthands for commands: when the first word of a command matches an alias,
+    /// it is replaced by the alias's command.
+    #[serde(default)]
+    pub aliases: BTreeMap<String, String>,
+    /// Values for `{placeholder}`s in commands, in addition to the built-in ones.
+    #[serde(default)]
+    pub variables: BTreeMap<String, String>,
+    /// Run commands without sourcing the shell's rc file, like `--no-rc`.
+    #[serde(default)]
+    pub no_rc: bool,
+    /// Environment variables set for the commands run in every directory.
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    /// Environment variables set for the commands run in the members of a group.
+    #[serde(default)]
+    pub group_env: BTreeMap<String, BTreeMap<String, String>>,
+    /// Environment variables set for the commands run in a directory, by name.
+    #[serde(default)]
+    pub dir_env: BTreeMap<String, BTreeMap<String, String>>,
+    /// Read a `.env` file from each directory, like `--dotenv`.
+    #[serde(default)]
+    pub dotenv: bool,
+    /// Script files that can be run by name with `--script`. Relative paths are
+    /// resolved from the directory loop is run in.
+    #[serde(default)]
+    pub scripts: BTreeMap<String, String>,
+    /// Named lists of steps that can be run in each directory with `--pipeline`.
+    #[serde(default)]
+    pub pipelines: BTreeMap<String, Vec<PipelineStep>>,
+    /// How the exit codes of the directories are combined into loop's exit code, like
+    /// `--exit-status`.
+    #[serde(default)]
+    pub exit_status: ExitPolicy,
 }
 
-/// Creates a .looprc file in the current directory with the default configuration.
+/// How loop combines the exit codes of the commands it ran into its own exit code.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum ExitPolicy {
+    /// The exit code of the first directory that failed.
+    #[default]
+    First,
+    /// The exit code of the last directory that failed.
+    Last,
+    /// The highest exit code.
+    Max,
+    /// The number of directories that failed.
+    Count,
+}
+
+/// One step of a pipeline.
+///
+/// In a configuration file a step is either a command string, or a table with `run`
+/// and optionally `name` and `continue_on_error`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(from = "StepDefinition")]
+pub struct PipelineStep {
+    /// The name shown in the output, instead of the command.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// The shell code to run.
+    pub run: String,
+    /// Run the remaining steps even if this one fails.
+    #[serde(default)]
+    pub continue_on_error: bool,
+}
+
+/// The forms a pipeline step can take in a configuration file.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StepDefinition {
+    Command(String),
+    Step(StepTable),
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct StepTable {
+    #[serde(default)]
+    name: Option<String>,
+    run: String,
+    #[serde(default)]
+    continue_on_error: bool,
+}
+
+impl From<StepDefinition> for PipelineStep {
+    fn from(definition: StepDefinition) -> PipelineStep {
+        match definition {
+            StepDefinition::Command(run) => PipelineStep::from_arg(&run),
+            StepDefinition::Step(step) => PipelineStep {
+                name: step.name,
+                run: step.run,
+                continue_on_error: step.continue_on_error,
+            },
+        }
+    }
+}
+
+impl PipelineStep {
+    /// Parses a `--step` value. As in a makefile, a leading `-` marks a step whose
+    /// failure does not stop the pipeline.
+    pub fn from_arg(arg: &str) -> PipelineStep {
+        let (run, continue_on_error) = match arg.strip_prefix('-') {
+            Some(run) => (run.trim_start(), true),
+            None => (arg, false),
+        };
+        PipelineStep {
+            name: None,
+            run: run.to_string(),
+            continue_on_error,
+        }
+    }
+
+    /// The name of the step, or its command if it has none.
+    pub fn label(&self) -> &str {
+        self.name.as_deref().unwrap_or(&self.run)
+    }
+}
+
+impl LoopConfig {
+    /// Returns the configuration with environment variables and `~` expanded.
+    ///
+    /// Every path- and command-valued field is passed through `interpolate`. This is
+    /// applied when a configuration is used rather than when it is loaded, so that
+    /// `loop config set` writes the file back with its placeholders intact.
+    pub fn interpolated(mut self) -> LoopConfig {
+        for dir in &mut self.ignore {
+            *dir = interpolate(dir);
+        }
+        for dirs in self.groups.values_mut() {
+            for dir in dirs {
+                *dir = interpolate(dir);
+            }
+        }
+        for command in self.aliases.values_mut() {
+            *command = interpolate(command);
+        }
+        for value in self.variables.values_mut() {
+            *value = interpolate(value);
+        }
+        for path in self.scripts.values_mut() {
+            *path = interpolate(path);
+        }
+        for step in self.pipelines.values_mut().flatten() {
+            step.run = interpolate(&step.run);
+        }
+        let env_maps = std::iter::once(&mut self.env)
+            .chain(self.group_env.values_mut())
+            .chain(self.dir_env.values_mut());
+        for values in env_maps {
+            for value in values.values_mut() {
+                *value = interpolate(value);
+            }
+        }
+        self
+    }
+}
+
+/// Expands `${VAR}` and `${VAR:-default}` from the environment, then a leading `~`.
+///
+/// `${VAR:-default}` uses the default when the variable is unset or empty. A `${VAR}`
+/// whose variable is unset is left as written, so that commands can still refer to
+/// variables that only exist when they run. `$${` produces a literal `${`.
+pub fn interpolate(value: &str) -> String {
+    expand_tilde(&interpolate_with(value, |name| std::env::var(name).ok()))
+}
+
+/// Expands `${VAR}` and `${VAR:-default}` using the given variable lookup.
+pub fn interpolate_with(value: &str, lookup: impl Fn(&str) -> Option<String>) -> String {
+    let mut result = String::with_capacity(value.len());
+    let mut rest = value;
+
+    while let Some(index) = rest.find('$') {
+        result.push_str(&rest[..index]);
+        rest = &rest[index..];
+
+        if let Some(after) = rest.strip_prefix("$${") {
+            result.push_str("${");
+            rest = after;
+            continue;
+        }
+
+        let expression = rest
+            .strip_prefix("${")
+            .and_then(|after| after.find('}').map(|end| &after[..end]));
+        let Some(expression) = expression else {
+            result.push('$');
+            rest = &rest[1..];
+            continue;
+        };
+
+        let (name, default) = match expression.split_once(":-") {
+            Some((name, default)) => (name, Some(default)),
+            None => (expression, None),
+        };
+        let valid_name =
+            !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+
+        match (valid_name, lookup(name), default) {
+            (true, Some(value), Some(default)) if value.is_empty() => result.push_str(default),
+            (true, Some(value), _) => result.push_str(&value),
+            (true, None, Some(default)) => result.push_str(default),
+            _ => result.push_str(&rest[..expression.len() + 3]),
+        }
+        rest = &rest[expression.len() + 3..];
+    }
+
+    result.push_str(rest);
+    result
+}
+
+/// Replaces a leading `~` or `~/` with the user's home directory.
+///
+/// Other uses of `~`, such as `~user` or a `~` in the middle of a value, are left as is.
+pub fn expand_tilde(value: &str) -> String {
+    let home = std::env::var("HOME").or_else(|_| std::env::var("USERPROFILE"));
+    match (value, home) {
+        ("~", Ok(home)) => home,
+        (value, Ok(home)) if value.starts_with("~/") => format!("{}{}", home, &value[1..]),
+        (value, _) => value.to_string(),
+    }
+}
+
+/// An error found while loading a configuration file.
+///
+/// The line and column are one-based and present whenever the underlying parser
+/// reports a location.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfigError {
+    pub path: PathBuf,
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+    pub message: String,
+}
+
+impl ConfigError {
+    fn new(message: impl Into<String>) -> ConfigError {
+        ConfigError {
+            path: PathBuf::new(),
+            line: None,
+            column: None,
+            message: message.into(),
+        }
+    }
+
+    fn at(mut self, line: usize, column: usize) -> ConfigError {
+        self.line = Some(line);
+        self.column = Some(column);
+        self
+    }
+
+    fn in_file(mut self, path: &Path) -> ConfigError {
+        self.path = path.to_path_buf();
+        self
+    }
+
+    fn from_json(err: serde_json::Error) -> ConfigError {
+        let error = ConfigError::new(strip_location(&err.to_string()));
+        match (err.line(), err.column()) {
+            (0, _) => error,
+            (line, column) => error.at(line, column),
+        }
+    }
+
+    fn from_toml(err: toml::de::Error, contents: &str) -> ConfigError {
+        let error = ConfigError::new(err.message().trim_end());
+        match err.span() {
+            Some(span) => {
+                let (line, column) = line_and_column(contents, span.start);
+                error.at(line, column)
+            }
+            None => error,
+        }
+    }
+
+    fn from_yaml(err: serde_yaml_ng::Error) -> ConfigError {
+        let error = ConfigError::new(strip_location(&err.to_string()));
+        match err.location() {
+            Some(location) => error.at(location.line(), location.column()),
+            None => error,
+        }
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.line, self.column) {
+            (Some(line), Some(column)) => write!(
+                f,
+                "{}:{}:{}: {}",
+                self.path.display(),
+                line,
+                column,
+                self.message
+            ),
+            _ => write!(f, "{}: {}", self.path.display(), self.message),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Removes the " at line X column Y" suffix serde_json and serde_yaml_ng append to their
+/// messages, since ConfigError reports the location itself.
+fn strip_location(message: &str) -> String {
+    match message.rfind(" at line ") {
+        Some(index) => message[..index].to_string(),
+        None => message.to_string(),
+    }
+}
+
+/// Converts a byte offset into a one-based line and column.
+fn line_and_column(contents: &str, offset: usize) -> (usize, usize) {
+    let before = &contents[..offset.min(contents.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before.len() - before.rfind('\n').map_or(0, |index| index + 1) + 1;
+    (line, column)
+}
+
+/// The file formats a loop configuration file can be written in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ConfigFormat {
+    Json,
+    Toml,
+    Yaml,
+}
+
+impl ConfigFormat {
+    /// Determines the format of a configuration file from its extension.
+    ///
+    /// Returns `None` for files without a recognised extension, such as a bare `.looprc`.
+    pub fn from_path(path: &Path) -> Option<ConfigFormat> {
+        match path.extension()?.to_str()? {
+            "json" => Some(ConfigFormat::Json),
+            "toml" => Some(ConfigFormat::Toml),
+            "yaml" | "yml" => Some(ConfigFormat::Yaml),
+            _ => None,
+        }
+    }
+
+    /// Guesses the format of a configuration file from its contents.
+    ///
+    /// Anything that looks like a JSON object is treated as JSON, then TOML is tried,
+    /// and YAML is used as the fallback.
+    pub fn detect(contents: &str) -> ConfigFormat {
+        if contents.trim_start().starts_with('{') {
+            ConfigFormat::Json
+        } else if toml::from_str::<toml::Table>(contents).is_ok() {
+            ConfigFormat::Toml
+        } else {
+            ConfigFormat::Yaml
+        }
+    }
+
+    /// The file name `loop --init` writes for this format.
+    pub fn default_file_name(self) -> &'static str {
+        match self {
+            ConfigFormat::Json => ".looprc",
+            ConfigFormat::Toml => ".looprc.toml",
+            ConfigFormat::Yaml => ".looprc.yaml",
+        }
+    }
+}
+
+/// Whether the configuration file `a` takes precedence over `b` in the same directory.
+fn precedes(a: &Path, b: &Path) -> bool {
+    let rank = |path: &Path| {
+        CONFIG_FILE_NAMES
+            .iter()
+            .position(|name| path.file_name() == Some(name.as_ref()))
+    };
+    rank(a) < rank(b)
+}
+
+/// Finds the configuration file in the given directory.
+///
+/// Candidates are checked in the order of `CONFIG_FILE_NAMES` and the first existing
+/// file is returned.
+pub fn find_config_file(dir: &Path) -> Option<PathBuf> {
+    CONFIG_FILE_NAMES
+        .iter()
+        .map(|name| dir.join(name))
+        .find(|path| path.is_file())
+}
+
+/// Parses configuration file contents in the given format.
+///
+/// Unknown keys are rejected. The returned error carries the line and column of the
+/// problem but no file path; `load_config_file` fills that in.
+pub fn parse_config(contents: &str, format: ConfigFormat) -> Result<LoopConfig, ConfigError> {
+    match format {
+        ConfigFormat::Json => serde_json::from_str(contents).map_err(ConfigError::from_json),
+        ConfigFormat::Toml => {
+            toml::from_str(contents).map_err(|err| ConfigError::from_toml(err, contents))
+        }
+        ConfigFormat::Yaml => serde_yaml_ng::from_str(contents).map_err(ConfigError::from_yaml),
+    }
+}
+
+/// Reads and parses the configuration file at the given path.
+///
+/// The format is taken from the file extension or, for a bare `.looprc`, detected
+/// from the contents.
+pub fn load_config_file(path: &Path) -> Result<LoopConfig, ConfigError> {
+    let contents =
+        fs::read_to_string(path).map_err(|err| ConfigError::new(err.to_string()).in_file(path))?;
+    let format = ConfigFormat::from_path(path).unwrap_or_else(|| ConfigFormat::detect(&contents));
+    parse_config(&contents, format).map_err(|err| err.in_file(path))
+}
+
+/// Loads the configuration for the given directory.
+///
+/// Returns the default configuration when the directory has no configuration file,
+/// and an error when the file exists but is invalid.
+pub fn load_config(dir: &Path) -> Result<LoopConfig, ConfigError> {
+    match find_config_file(dir) {
+        Some(path) => load_config_file(&path),
+        None => Ok(LoopConfig::default()),
+    }
+}
+
+/// Returns the top-level keys that are set explicitly in the configuration file.
+///
+/// Keys missing from the file take their default values; this is what lets
+/// `loop config show` tell the two apart.
+pub fn file_keys(path: &Path) -> Result<Vec<String>, ConfigError> {
+    let contents =
+        fs::read_to_string(path).map_err(|err| ConfigError::new(err.to_string()).in_file(path))?;
+    let format = ConfigFormat::from_path(path).unwrap_or_else(|| ConfigFormat::detect(&contents));
+    let value: serde_json::Value = match format {
+        ConfigFormat::Json => serde_json::from_str(&contents).map_err(ConfigError::from_json),
+        ConfigFormat::Toml => {
+            toml::from_str(&contents).map_err(|err| ConfigError::from_toml(err, &contents))
+        }
+        ConfigFormat::Yaml => serde_yaml_ng::from_str(&contents).map_err(ConfigError::from_yaml),
+    }
+    .map_err(|err| err.in_file(path))?;
+
+    Ok(value
+        .as_object()
+        .map(|object| object.keys().cloned().collect())
+        .unwrap_or_default())
+}
+
+/// Looks up a value in the configuration by its dotted key, such as `ignore`.
+pub fn get_value(config: &LoopConfig, key: &str) -> Option<serde_json::Value> {
+    let mut value = serde_json::to_value(config).ok()?;
+    for part in key.split('.') {
+        value = value.get_mut(part)?.take();
+    }
+    Some(value)
+}
+
+/// Returns a copy of the configuration with the value under the dotted key replaced.
+///
+/// A single value is parsed as JSON when possible and taken as a plain string
+/// otherwise, or when the parsed value does not fit the key, so `env.PORT 8080` sets
+/// the string `"8080"`. Several values always form a list of strings. A single value
+/// given for a list key is wrapped into a one-element list. The result is validated
+/// like a configuration file, so unknown keys and mistyped values are rejected.
+pub fn set_value(
+    config: &LoopConfig,
+    key: &str,
+    values: &[String],
+) -> Result<LoopConfig, ConfigError> {
+    let root = serde_json::to_value(config).map_err(ConfigError::from_json)?;
+
+    let candidates = match values {
+        [value] => {
+            let raw = serde_json::Value::String(value.clone());
+            match serde_json::from_str(value) {
+                Ok(parsed) if parsed != raw => vec![parsed, raw],
+                _ => vec![raw],
+            }
+        }
+        values => vec![serde_json::Value::Array(
+            values
+                .iter()
+                .cloned()
+                .map(serde_json::Value::String)
+                .collect(),
+        )],
+    };
+
+    let mut first_error = None;
+    for candidate in candidates {
+        match serde_json::from_value(replace_value(root.clone(), key, candidate)) {
+            Ok(config) => return Ok(config),
+            Err(err) => {
+                first_error.get_or_insert(err);
+            }
+        }
+    }
+    Err(ConfigError::from_json(first_error.unwrap()))
+}
+
+/// Replaces the value under the dotted key, creating objects along the way. A value
+/// that is not a list, put under a key that holds one, is wrapped into a list.
+fn replace_value(
+    mut root: serde_json::Value,
+    key: &str,
+    value: serde_json::Value,
+) -> serde_json::Value {
+    let mut slot = &mut root;
+    for part in key.split('.') {
+        if !slot.is_object() {
+            *slot = serde_json::Value::Object(serde_json::Map::new());
+        }
+        slot = slot
+            .as_object_mut()
+            .unwrap()
+            .entry(part)
+            .or_insert(serde_json::Value::Null);
+    }
+
+    *slot = if slot.is_array() && !value.is_array() {
+        serde_json::Value::Array(vec![value])
+    } else {
+        value
+    };
+    root
+}
+
+/// Writes the configuration to the given file.
 ///
-/// This function creates a .looprc file in the current directory with the default configuration.
-/// The default configuration is a list of directories to ignore, such as .git.
-pub fn create_looprc() {
-    use std::env;
+/// The format follows the file extension, then the existing contents of the file,
+/// and defaults to JSON.
+pub fn write_config_file(path: &Path, config: &LoopConfig) -> Result<(), ConfigError> {
+    let format = ConfigFormat::from_path(path).unwrap_or_else(|| {
+        fs::read_to_string(path)
+            .map(|contents| ConfigFormat::detect(&contents))
+            .unwrap_or(ConfigFormat::Json)
+    });
+    let contents = serialize_config(config, format).map_err(|err| err.in_file(path))?;
+    fs::write(path, contents).map_err(|err| ConfigError::new(err.to_string()).in_file(path))
+}
+
+/// Sets the value under the dotted key in a configuration file, creating the file if
+/// needed, and leaves the rest of the file as written.
+///
+/// The value should come from a configuration checked by `set_value`. TOML files keep
+/// their comments and layout; JSON and YAML files keep their keys and order, but are
+/// written out again, so YAML comments are lost.
+pub fn update_config_file(
+    path: &Path,
+    key: &str,
+    value: &serde_json::Value,
+) -> Result<(), ConfigError> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(err) => return Err(ConfigError::new(err.to_string()).in_file(path)),
+    };
+    let empty = contents.trim().is_empty();
+    let format = ConfigFormat::from_path(path).unwrap_or(if empty {
+        ConfigFormat::Json
+    } else {
+        ConfigFormat::detect(&contents)
+    });
+    let updated =
+        updated_contents(&contents, format, key, value).map_err(|err| err.in_file(path))?;
+    fs::write(path, updated).map_err(|err| ConfigError::new(err.to_string()).in_file(path))
+}
+
+/// Returns the contents of a configuration file with the value under the dotted key
+/// replaced. Empty contents are taken as an empty configuration.
+fn updated_contents(
+    contents: &str,
+    format: ConfigFormat,
+    key: &str,
+    value: &serde_json::Value,
+) -> Result<String, ConfigError> {
+    let empty = contents.trim().is_empty();
+    let parts: Vec<&str> = key.split('.').collect();
+
+    let updated = match format {
+        ConfigFormat::Json => {
+            let mut root = if empty {
+                serde_json::Value::Object(serde_json::Map::new())
+            } else {
+                serde_json::from_str(contents).map_err(ConfigError::from_json)?
+            };
+            let mut slot = &mut root;
+            for part in &parts {
+                if !slot.is_object() {
+                    *slot = serde_json::Value::Object(serde_json::Map::new());
+                }
+                slot = slot
+                    .as_object_mut()
+                    .unwrap()
+                    .entry(*part)
+                    .or_insert(serde_json::Value::Null);
+            }
+            *slot = value.clone();
+            serde_json::to_string_pretty(&root).map_err(ConfigError::from_json)? + "\n"
+        }
+        ConfigFormat::Toml => {
+            let mut document: toml_edit::DocumentMut =
+                contents.parse().map_err(|err: toml_edit::TomlError| {
+                    let error = ConfigError::new(err.message().trim_end());
+                    match err.span() {
+                        Some(span) => {
+                            let (line, column) = line_and_column(contents, span.start);
+                            error.at(line, column)
+                        }
+                        None => error,
+                    }
+                })?;
+            let Some((last, parents)) = parts.split_last() else {
+                return Ok(contents.to_string());
+            };
+            let mut table: &mut dyn toml_edit::TableLike = document.as_table_mut();
+            for part in parents {
+                table = table
+                    .entry(part)
+                    .or_insert(toml_edit::table())
+                    .as_table_like_mut()
+                    .ok_or_else(|| ConfigError::new(format!("{} is not a table", part)))?;
+            }
+            match toml_value(value) {
+                // An existing entry is replaced in place, keeping the comments around it
+                Some(mut new) => match table.get_mut(last) {
+                    Some(item) => {
+                        if let Some(old) = item.as_value() {
+                            *new.decor_mut() = old.decor().clone();
+                        }
+                        *item = toml_edit::Item::Value(new);
+                    }
+                    None => {
+                        table.insert(last, toml_edit::Item::Value(new));
+                    }
+                },
+                None => {
+                    table.remove(last);
+                }
+            }
+            document.to_string()
+        }
+        ConfigFormat::Yaml => {
+            let mut root = if empty {
+                serde_yaml_ng::Value::Mapping(serde_yaml_ng::Mapping::new())
+            } else {
+                serde_yaml_ng::from_str(contents).map_err(ConfigError::from_yaml)?
+            };
+            let mut slot = &mut root;
+            for part in &parts {
+                if !slot.is_mapping() {
+                    *slot = serde_yaml_ng::Value::Mapping(serde_yaml_ng::Mapping::new());
+                }
+                slot = slot
+                    .as_mapping_mut()
+                    .unwrap()
+                    .entry(serde_yaml_ng::Value::String(part.to_string()))
+                    .or_insert(serde_yaml_ng::Value::Null);
+            }
+            *slot = serde_yaml_ng::to_value(value).map_err(ConfigError::from_yaml)?;
+            serde_yaml_ng::to_string(&root).map_err(ConfigError::from_yaml)?
+        }
+    };
+    Ok(updated)
+}
+
+/// Converts a JSON value to a TOML value. TOML has no null, so `None` is returned for
+/// it, and for nulls in lists and tables the entries are left out.
+fn toml_value(value: &serde_json::Value) -> Option<toml_edit::Value> {
+    Some(match value {
+        serde_json::Value::Null => return None,
+        serde_json::Value::Bool(value) => (*value).into(),
+        serde_json::Value::Number(number) => match number.as_i64() {
+            Some(integer) => integer.into(),
+            None => number.as_f64()?.into(),
+        },
+        serde_json::Value::String(value) => value.as_str().into(),
+        serde_json::Value::Array(items) => items
+            .iter()
+            .filter_map(toml_value)
+            .collect::<toml_edit::Array>()
+            .into(),
+        serde_json::Value::Object(entries) => entries
+            .iter()
+            .filter_map(|(key, value)| Some((key.as_str(), toml_value(value)?)))
+            .collect::<toml_edit::InlineTable>()
+            .into(),
+    })
+}
+
+/// Serializes a configuration into the given format.
+///
+/// Settings left at their defaults are not written. This fails if the configuration
+/// holds a value the format cannot represent.
+pub fn serialize_config(config: &LoopConfig, format: ConfigFormat) -> Result<String, ConfigError> {
+    let mut value = serde_json::to_value(config).map_err(ConfigError::from_json)?;
+    let defaults = serde_json::to_value(LoopConfig::default()).map_err(ConfigError::from_json)?;
+    if let (Some(entries), Some(defaults)) = (value.as_object_mut(), defaults.as_object()) {
+        entries.retain(|key, value| defaults.get(key) != Some(value));
+    }
+    match format {
+        ConfigFormat::Json => serde_json::to_string_pretty(&value).map_err(ConfigError::from_json),
+        ConfigFormat::Toml => {
+            toml::to_string_pretty(&value).map_err(|err| ConfigError::new(err.to_string()))
+        }
+        ConfigFormat::Yaml => serde_yaml_ng::to_string(&value).map_err(ConfigError::from_yaml),
+    }
+}
+
+/// An error from `loop --init`.
+#[derive(Debug)]
+pub enum InitError {
+    /// A configuration file already exists and `--force` was not given.
+    AlreadyExists(PathBuf),
+    /// The new file would be ignored because `by` takes precedence over it.
+    Shadowed { path: PathBuf, by: PathBuf },
+    /// The suggested configuration could not be serialized.
+    Serialize(ConfigError),
+    /// The configuration file could not be written.
+    Io(PathBuf, io::Error),
+}
+
+impl fmt::Display for InitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InitError::AlreadyExists(path) => write!(
+                f,
+                "{} already exists; use --force to overwrite it",
+                path.display()
+            ),
+            InitError::Shadowed { path, by } => write!(
+                f,
+                "{} would be ignored because {} takes precedence; remove it first",
+                path.display(),
+                by.display()
+            ),
+            InitError::Serialize(err) => {
+                write!(f, "failed to serialize the configuration: {}", err)
+            }
+            InitError::Io(path, err) => write!(f, "failed to write {}: {}", path.display(), err),
+        }
+    }
+}
+
+impl std::error::Error for InitError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            InitError::Serialize(err) => Some(err),
+            InitError::Io(_, err) => Some(err),
+            _ => None,
+        }
+    }
+}
 
-    let config = LoopConfig {
+/// Build and dependency folders that are ignored when found in the directory.
+const BUILD_FOLDERS: &[&str] = &[
+    "target",
+    "node_modules",
+    ".venv",
+    "venv",
+    "__pycache__",
+    "dist",
+    "build",
+];
+
+/// A kind of project recognised by `suggest_config`.
+struct ProjectKind {
+    /// The group the project's directories are added to.
+    group: &'static str,
+    /// Files or folders whose presence marks a directory as this kind of project.
+    markers: &'static [&'static str],
+    /// Aliases suggested when at least one such project is found.
+    aliases: &'static [(&'static str, &'static str)],
+}
+
+const PROJECT_KINDS: &[ProjectKind] = &[
+    ProjectKind {
+        group: "git",
+        markers: &[".git"],
+        aliases: &[
+            ("st", "git status --short --branch"),
+            ("up", "git pull --ff-only"),
+        ],
+    },
+    ProjectKind {
+        group: "rust",
+        markers: &["Cargo.toml"],
+        aliases: &[("test-rust", "cargo test")],
+    },
+    ProjectKind {
+        group: "node",
+        markers: &["package.json"],
+        aliases: &[("test-node", "npm test")],
+    },
+    ProjectKind {
+        group: "python",
+        markers: &["pyproject.toml", "setup.py", "requirements.txt"],
+        aliases: &[("test-python", "python -m pytest")],
+    },
+];
+
+/// Suggests a configuration for the given directory by looking at its children.
+///
+/// `.git` and any common build folders present are ignored, each kind of project found
+/// (git repositories, Cargo, npm and Python projects) becomes a group listing those
+/// directories, and aliases for the usual commands of those projects are added.
+pub fn suggest_config(dir: &Path) -> LoopConfig {
+    let mut config = LoopConfig {
         ignore: vec![".git".to_string()],
+        ..LoopConfig::default()
     };
-    let json = serde_json::to_string_pretty(&config).unwrap();
-    let file_path = ".looprc";
-    fs::write(file_path, &json).unwrap();
-    let full_path = env::current_dir().unwrap().join(file_path);
-    println!(
-        "Created .looprc file at {} with content: {}",
-        full_path.display(),
-        json
-    );
+
+    let mut children: Vec<(String, PathBuf)> = fs::read_dir(dir)
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok())
+                .filter(|entry| entry.path().is_dir())
+                .map(|entry| {
+                    (
+                        entry.file_name().to_string_lossy().into_owned(),
+                        entry.path(),
+                    )
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    children.sort();
+
+    for (name, path) in &children {
+        if BUILD_FOLDERS.contains(&name.as_str()) {
+            config.ignore.push(name.clone());
+            continue;
+        }
+        if name.starts_with('.') {
+            continue;
+        }
+        for kind in PROJECT_KINDS {
+            if kind.markers.iter().any(|marker| path.join(marker).exists()) {
+                config
+                    .groups
+                    .entry(kind.group.to_string())
+                    .or_default()
+                    .push(name.clone());
+                for (alias, command) in kind.aliases {
+                    config
+                        .aliases
+                        .insert(alias.to_string(), command.to_string());
+                }
+            }
+        }
+    }
+
+    config
+}
+
+/// Creates a configuration file in the given directory for `loop --init`.
+///
+/// The contents come from `suggest_config`. JSON is written to `.looprc`, while TOML
+/// and YAML are written to `.looprc.toml` and `.looprc.yaml` respectively. Unless
+/// `force` is set, this refuses to run when the directory already has any
+/// configuration file, since a new file would either overwrite or be shadowed by it.
+/// Even with `force`, only a file in the new file's place is overwritten: a file that
+/// takes precedence over it is left alone and reported as `InitError::Shadowed`.
+pub fn init_config(dir: &Path, format: ConfigFormat, force: bool) -> Result<PathBuf, InitError> {
+    let path = dir.join(format.default_file_name());
+    match find_config_file(dir) {
+        Some(existing) if !force => return Err(InitError::AlreadyExists(existing)),
+        Some(existing) if existing != path && precedes(&existing, &path) => {
+            return Err(InitError::Shadowed { path, by: existing })
+        }
+        _ => {}
+    }
+
+    let contents = serialize_config(&suggest_config(dir), format).map_err(InitError::Serialize)?;
+    fs::write(&path, &contents).map_err(|err| InitError::Io(path.clone(), err))?;
+    outln!("Created {} with content:\n{}", path.display(), contents);
+    Ok(path)
+}
+
+/// Creates a .looprc file in the current directory with the default configuration.
+///
+/// This function creates a .looprc file in the current directory with a suggested
+/// configuration, such as ignoring .git, and returns its path. It fails if a
+/// configuration file already exists; use `init_config` to overwrite one.
+pub fn create_looprc() -> Result<PathBuf, LoopError> {
+    Ok(init_config(Path::new("."), ConfigFormat::Json, false)?)
 }
 
 /// Reads the .looprc configuration file from the current directory.
 ///
-/// This function attempts to read and parse the .looprc file, returning
-/// a LoopConfig struct with the parsed configuration. If the file doesn't
-/// exist or can't be parsed, it returns a default configuration.
-pub fn read_looprc() -> LoopConfig {
-    match fs::read_to_string(".looprc") {
-        Ok(contents) => serde_json::from_str(&contents).unwrap_or_else(|err| {
-            eprintln!("Failed to parse .looprc: {}", err);
-            LoopConfig { ignore: vec![] }
-        }),
-        Err(_err) => {
-            // Fail silently and continue
-            LoopConfig { ignore: vec![] }
+/// This function looks for any of the `CONFIG_FILE_NAMES` and returns the parsed
+/// LoopConfig with variables expanded. If no file exists it returns a default
+/// configuration; a file that can't be parsed is an error.
+pub fn read_looprc() -> Result<LoopConfig, LoopError> {
+    Ok(load_config(Path::new("."))?.interpolated())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_format() {
+        assert_eq!(
+            ConfigFormat::detect(r#"{"ignore": [".git"]}"#),
+            ConfigFormat::Json
+        );
+        assert_eq!(
+            ConfigFormat::detect("# comment\nignore = [\".git\"]\n"),
+            ConfigFormat::Toml
+        );
+        assert_eq!(
+            ConfigFormat::detect("# comment\nignore:\n  - .git\n"),
+            ConfigFormat::Yaml
+        );
+    }
+
+    #[test]
+    fn test_parse_config_formats() {
+        let expected = LoopConfig {
+            ignore: vec![".git".to_string(), "target".to_string()],
+            ..LoopConfig::default()
+        };
+
+        let json = r#"{"ignore": [".git", "target"]}"#;
+        let toml = "ignore = [\".git\", \"target\"]";
+        let yaml = "ignore:\n  - .git\n  - target\n";
+
+        assert_eq!(parse_config(json, ConfigFormat::Json).unwrap(), expected);
+        assert_eq!(parse_config(toml, ConfigFormat::Toml).unwrap(), expected);
+        assert_eq!(parse_config(yaml, ConfigFormat::Yaml).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_parse_env_sections() {
+        let toml = r#"
+            dotenv = true
+
+            [env]
+            RUST_LOG = "info"
+
+            [group_env.node]
+            NODE_ENV = "production"
+
+            [dir_env.api]
+            AWS_PROFILE = "dev"
+        "#;
+
+        let config = parse_config(toml, ConfigFormat::Toml).unwrap();
+        assert!(config.dotenv);
+        assert_eq!(config.env["RUST_LOG"], "info");
+        assert_eq!(config.group_env["node"]["NODE_ENV"], "production");
+        assert_eq!(config.dir_env["api"]["AWS_PROFILE"], "dev");
+    }
+
+    #[test]
+    fn test_parse_pipelines() {
+        let yaml = r#"
+pipelines:
+  update:
+    - git pull
+    - "- npm run lint"
+    - name: test
+      run: npm test
+      continue_on_error: true
+"#;
+
+        let config = parse_config(yaml, ConfigFormat::Yaml).unwrap();
+        let steps = &config.pipelines["update"];
+        assert_eq!(steps[0], PipelineStep::from_arg("git pull"));
+        assert_eq!(steps[1].run, "npm run lint");
+        assert!(steps[1].continue_on_error);
+        assert_eq!(steps[2].label(), "test");
+        assert!(steps[2].continue_on_error);
+
+        for format in [ConfigFormat::Json, ConfigFormat::Toml, ConfigFormat::Yaml] {
+            let contents = serialize_config(&config, format).unwrap();
+            assert_eq!(parse_config(&contents, format).unwrap(), config);
         }
+
+        let unknown = "pipelines:\n  update:\n    - run: ls\n      retries: 3\n";
+        assert!(parse_config(unknown, ConfigFormat::Yaml).is_err());
+    }
+
+    #[test]
+    fn test_serialize_round_trip() {
+        let config = LoopConfig {
+            ignore: vec![".git".to_string()],
+            ..LoopConfig::default()
+        };
+
+        for format in [ConfigFormat::Json, ConfigFormat::Toml, ConfigFormat::Yaml] {
+            let contents = serialize_config(&config, format).unwrap();
+            assert_eq!(parse_config(&contents, format).unwrap(), config);
+        }
+    }
+
+    #[test]
+    fn test_find_config_file_precedence() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        assert!(find_config_file(temp_dir.path()).is_none());
+
+        fs::write(temp_dir.path().join("loop.toml"), "ignore = []").unwrap();
+        assert_eq!(
+            find_config_file(temp_dir.path()),
+            Some(temp_dir.path().join("loop.toml"))
+        );
+
+        fs::write(temp_dir.path().join(".looprc.yaml"), "ignore: []").unwrap();
+        assert_eq!(
+            find_config_file(temp_dir.path()),
+            Some(temp_dir.path().join(".looprc.yaml"))
+        );
+    }
+
+    #[test]
+    fn test_unknown_keys_are_rejected() {
+        let err = parse_config(
+            "{\n  \"ignore\": [],\n  \"ignroe\": []\n}",
+            ConfigFormat::Json,
+        )
+        .unwrap_err();
+        assert!(err.message.contains("unknown field `ignroe`"));
+        assert_eq!(err.line, Some(3));
+
+        let err = parse_config("ignore = []\nignroe = []\n", ConfigFormat::Toml).unwrap_err();
+        assert!(err.message.contains("unknown field `ignroe`"));
+        assert_eq!((err.line, err.column), (Some(2), Some(1)));
+
+        let err = parse_config("ignore: []\nignroe: []\n", ConfigFormat::Yaml).unwrap_err();
+        assert!(err.message.contains("unknown field `ignroe`"));
+        assert_eq!(err.line, Some(2));
+    }
+
+    #[test]
+    fn test_load_config_reports_path_and_location() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join(".looprc");
+        fs::write(&path, "{\n  \"ignore\": [\".git\",]\n}").unwrap();
+
+        let err = load_config(temp_dir.path()).unwrap_err();
+        assert_eq!(err.path, path);
+        assert_eq!(err.line, Some(2));
+        assert!(err
+            .to_string()
+            .starts_with(&format!("{}:2:", path.display())));
+    }
+
+    #[test]
+    fn test_load_config_missing_file_is_default() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        assert_eq!(load_config(temp_dir.path()).unwrap(), LoopConfig::default());
+    }
+
+    #[test]
+    fn test_get_and_set_value() {
+        let config = LoopConfig {
+            ignore: vec![".git".to_string()],
+            ..LoopConfig::default()
+        };
+        assert_eq!(
+            get_value(&config, "ignore"),
+            Some(serde_json::json!([".git"]))
+        );
+        assert_eq!(get_value(&config, "missing"), None);
+
+        let updated = set_value(&config, "ignore", &["target".to_string()]).unwrap();
+        assert_eq!(updated.ignore, vec!["target".to_string()]);
+
+        let updated = set_value(
+            &config,
+            "ignore",
+            &[".git".to_string(), "node_modules".to_string()],
+        )
+        .unwrap();
+        assert_eq!(updated.ignore, vec![".git", "node_modules"]);
+
+        let updated = set_value(&config, "ignore", &[r#"["a", "b"]"#.to_string()]).unwrap();
+        assert_eq!(updated.ignore, vec!["a", "b"]);
+
+        let err = set_value(&config, "ignroe", &["target".to_string()]).unwrap_err();
+        assert!(err.message.contains("unknown field `ignroe`"));
+        assert!(err.line.is_none());
+    }
+
+    #[test]
+    fn test_set_value_keeps_strings_that_look_like_json() {
+        let config = LoopConfig::default();
+
+        let updated = set_value(&config, "env.PORT", &["8080".to_string()]).unwrap();
+        assert_eq!(updated.env["PORT"], "8080");
+        let updated = set_value(&config, "aliases.t", &["true".to_string()]).unwrap();
+        assert_eq!(updated.aliases["t"], "true");
+        let updated = set_value(&config, "ignore", &["null".to_string()]).unwrap();
+        assert_eq!(updated.ignore, vec!["null"]);
+
+        let updated = set_value(&config, "no_rc", &["true".to_string()]).unwrap();
+        assert!(updated.no_rc);
+        let err = set_value(&config, "no_rc", &["yes".to_string()]).unwrap_err();
+        assert!(
+            err.message.contains("expected a boolean"),
+            "{}",
+            err.message
+        );
+    }
+
+    #[test]
+    fn test_write_config_file_keeps_format() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let config = LoopConfig {
+            ignore: vec!["target".to_string()],
+            ..LoopConfig::default()
+        };
+
+        let toml_path = temp_dir.path().join(".looprc.toml");
+        write_config_file(&toml_path, &config).unwrap();
+        assert!(fs::read_to_string(&toml_path)
+            .unwrap()
+            .starts_with("ignore = [\"target\"]\n"));
+
+        let bare_path = temp_dir.path().join(".looprc");
+        fs::write(&bare_path, "ignore: []\n").unwrap();
+        write_config_file(&bare_path, &config).unwrap();
+        assert_eq!(load_config_file(&bare_path).unwrap(), config);
+        assert!(fs::read_to_string(&bare_path)
+            .unwrap()
+            .starts_with("ignore:"));
+    }
+
+    #[test]
+    fn test_update_config_file_keeps_the_rest() {
+        let temp_dir = tempfile::tempdir().unwrap();
+
+        let toml_path = temp_dir.path().join(".looprc.toml");
+        fs::write(
+            &toml_path,
+            "# shared settings\nignore = [\"target\"] # build output\n\n[aliases]\nst = \"git status\"\n",
+        )
+        .unwrap();
+        update_config_file(&toml_path, "ignore", &serde_json::json!(["target", "dist"])).unwrap();
+        update_config_file(&toml_path, "groups.web", &serde_json::json!(["app"])).unwrap();
+        assert_eq!(
+            fs::read_to_string(&toml_path).unwrap(),
+            "# shared settings\nignore = [\"target\", \"dist\"] # build output\n\n[aliases]\nst = \"git status\"\n\n[groups]\nweb = [\"app\"]\n"
+        );
+
+        let json_path = temp_dir.path().join(".looprc");
+        fs::write(&json_path, r#"{"no_rc": true, "ignore": []}"#).unwrap();
+        update_config_file(&json_path, "ignore", &serde_json::json!([".git"])).unwrap();
+        assert_eq!(
+            file_keys(&json_path).unwrap(),
+            vec!["no_rc".to_string(), "ignore".to_string()]
+        );
+        assert_eq!(
+            load_config_file(&json_path).unwrap(),
+            LoopConfig {
+                ignore: vec![".git".to_string()],
+                no_rc: true,
+                ..LoopConfig::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_update_config_file_errors_name_the_file() {
+        let temp_dir = tempfile::tempdir().unwrap();
+
+        let toml_path = temp_dir.path().join(".looprc.toml");
+        fs::write(&toml_path, "ignore = [\n").unwrap();
+        let err = update_config_file(&toml_path, "no_rc", &serde_json::json!(true)).unwrap_err();
+        assert_eq!(err.path, toml_path);
+        assert!(err.line.is_some());
+
+        let json_path = temp_dir.path().join(".looprc");
+        fs::write(&json_path, "{\"ignore\": [").unwrap();
+        let err = update_config_file(&json_path, "no_rc", &serde_json::json!(true)).unwrap_err();
+        assert_eq!(err.path, json_path);
+        assert_eq!(err.line, Some(1));
+    }
+
+    #[test]
+    fn test_serialize_config_leaves_out_defaults() {
+        let config = LoopConfig {
+            ignore: vec![".git".to_string()],
+            ..LoopConfig::default()
+        };
+        assert_eq!(
+            serialize_config(&config, ConfigFormat::Toml).unwrap(),
+            "ignore = [\".git\"]\n"
+        );
+    }
+
+    #[test]
+    fn test_suggest_config() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root = temp_dir.path();
+        for dir in ["api", "web", "tools", "target", "node_modules", ".cache"] {
+            fs::create_dir(root.join(dir)).unwrap();
+        }
+        fs::create_dir(root.join("api/.git")).unwrap();
+        fs::write(root.join("api/Cargo.toml"), "").unwrap();
+        fs::create_dir(root.join("web/.git")).unwrap();
+        fs::write(root.join("web/package.json"), "{}").unwrap();
+        fs::write(root.join("tools/requirements.txt"), "").unwrap();
+
+        let config = suggest_config(root);
+        assert_eq!(config.ignore, vec![".git", "node_modules", "target"]);
+        assert_eq!(config.groups["git"], vec!["api", "web"]);
+        assert_eq!(config.groups["rust"], vec!["api"]);
+        assert_eq!(config.groups["node"], vec!["web"]);
+        assert_eq!(config.groups["python"], vec!["tools"]);
+        assert_eq!(config.aliases["st"], "git status --short --branch");
+        assert!(config.aliases.contains_key("test-rust"));
+    }
+
+    #[test]
+    fn test_init_config_refuses_to_overwrite() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        fs::write(temp_dir.path().join(".looprc.yaml"), "ignore: []\n").unwrap();
+
+        let err = init_config(temp_dir.path(), ConfigFormat::Json, false).unwrap_err();
+        assert!(
+            matches!(err, InitError::AlreadyExists(ref path) if path.ends_with(".looprc.yaml"))
+        );
+        assert!(!temp_dir.path().join(".looprc").exists());
+
+        let path = init_config(temp_dir.path(), ConfigFormat::Yaml, true).unwrap();
+        assert_eq!(load_config_file(&path).unwrap().ignore, vec![".git"]);
+    }
+
+    #[test]
+    fn test_init_config_refuses_shadowed_file() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        fs::write(temp_dir.path().join(".looprc"), "{\"ignore\": [\"keep\"]}").unwrap();
+
+        let err = init_config(temp_dir.path(), ConfigFormat::Toml, true).unwrap_err();
+        assert!(
+            matches!(err, InitError::Shadowed { ref by, .. } if by.ends_with(".looprc")),
+            "{:?}",
+            err
+        );
+        assert!(!temp_dir.path().join(".looprc.toml").exists());
+
+        fs::remove_file(temp_dir.path().join(".looprc")).unwrap();
+        fs::write(temp_dir.path().join(".looprc.yaml"), "ignore: []\n").unwrap();
+        let path = init_config(temp_dir.path(), ConfigFormat::Json, true).unwrap();
+        assert_eq!(find_config_file(temp_dir.path()), Some(path));
+    }
+
+    #[test]
+    fn test_interpolate_with() {
+        let lookup = |name: &str| match name {
+            "ROOT" => Some("/srv".to_string()),
+            "EMPTY" => Some(String::new()),
+            _ => None,
+        };
+
+        assert_eq!(interpolate_with("${ROOT}/app", lookup), "/srv/app");
+        assert_eq!(interpolate_with("${MISSING:-build}", lookup), "build");
+        assert_eq!(interpolate_with("${EMPTY:-fallback}", lookup), "fallback");
+        assert_eq!(interpolate_with("a${EMPTY}b", lookup), "ab");
+        assert_eq!(interpolate_with("${MISSING}", lookup), "${MISSING}");
+        assert_eq!(interpolate_with("$${ROOT}", lookup), "${ROOT}");
+        assert_eq!(interpolate_with("$ROOT and $", lookup), "$ROOT and $");
+        assert_eq!(interpolate_with("${not valid}", lookup), "${not valid}");
+        assert_eq!(interpolate_with("${ROOT", lookup), "${ROOT");
+    }
+
+    #[test]
+    fn test_expand_tilde() {
+        let home = std::env::var("HOME").unwrap();
+        assert_eq!(expand_tilde("~"), home);
+        assert_eq!(expand_tilde("~/src"), format!("{}/src", home));
+        assert_eq!(expand_tilde("~user/src"), "~user/src");
+        assert_eq!(expand_tilde("src/~"), "src/~");
     }
 }
@@ -0,0 +1,223 @@
+use crate::config::LoopConfig;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// The name of the file read from each directory when `.env` loading is enabled.
+pub const DOTENV_FILE_NAME: &str = ".env";
+
+/// Where a variable in a directory's environment was set.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EnvSource {
+    /// The `env` section of the configuration.
+    Config,
+    /// The `group_env` entry of the named group.
+    Group(String),
+    /// The `dir_env` entry of the directory.
+    Directory,
+    /// The `.env` file in the directory.
+    DotEnv(PathBuf),
+}
+
+impl fmt::Display for EnvSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EnvSource::Config => write!(f, "env"),
+            EnvSource::Group(group) => write!(f, "group_env.{}", group),
+            EnvSource::Directory => write!(f, "dir_env"),
+            EnvSource::DotEnv(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+/// A variable set for the commands run in a directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EnvVar {
+    pub key: String,
+    pub value: String,
+    pub source: EnvSource,
+}
+
+/// An error reading a directory's `.env` file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EnvError {
+    pub path: PathBuf,
+    pub message: String,
+}
+
+impl fmt::Display for EnvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.path.display(), self.message)
+    }
+}
+
+impl std::error::Error for EnvError {}
+
+/// The environment variables configured for the commands loop runs.
+///
+/// Variables are layered from least to most specific: the `env` section, then the
+/// `group_env` entries of every group the directory belongs to (in group name order),
+/// then the directory's `dir_env` entry, and finally the directory's `.env` file when
+/// `dotenv` is set. Each layer overrides the variables of the layers before it, and all
+/// of them override the variables inherited from loop's own environment.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Environment {
+    pub global: BTreeMap<String, String>,
+    pub groups: BTreeMap<String, BTreeMap<String, String>>,
+    pub directories: BTreeMap<String, BTreeMap<String, String>>,
+    /// The members of each group, used to find the groups a directory belongs to.
+    pub group_members: BTreeMap<String, Vec<String>>,
+    /// Read a `.env` file from each directory.
+    pub dotenv: bool,
+}
+
+impl Environment {
+    /// Builds the environment from the configuration. `.env` files are read when either
+    /// `dotenv` or the configuration's `dotenv` setting is set.
+    pub fn from_config(config: &LoopConfig, dotenv: bool) -> Environment {
+        Environment {
+            global: config.env.clone(),
+            groups: config.group_env.clone(),
+            directories: config.dir_env.clone(),
+            group_members: config.groups.clone(),
+            dotenv: dotenv || config.dotenv,
+        }
+    }
+
+    /// Resolves the variables set for a directory, sorted by name.
+    ///
+    /// Directories are matched by name, as in `groups` and `ignore`.
+    pub fn resolve(&self, dir: &Path) -> Result<Vec<EnvVar>, EnvError> {
+        let dir_name = dir.file_name().unwrap_or_default().to_string_lossy();
+        let mut vars: BTreeMap<String, (String, EnvSource)> = BTreeMap::new();
+        let mut set = |values: &BTreeMap<String, String>, source: EnvSource| {
+            for (key, value) in values {
+                vars.insert(key.clone(), (value.clone(), source.clone()));
+            }
+        };
+
+        set(&self.global, EnvSource::Config);
+        for (group, values) in &self.groups {
+            let is_member = self
+                .group_members
+                .get(group)
+                .is_some_and(|members| members.iter().any(|member| *member == dir_name));
+            if is_member {
+                set(values, EnvSource::Group(group.clone()));
+            }
+        }
+        if let Some(values) = self.directories.get(dir_name.as_ref()) {
+            set(values, EnvSource::Directory);
+        }
+
+        if self.dotenv {
+            let path = dir.join(DOTENV_FILE_NAME);
+            if path.is_file() {
+                set(&read_dotenv(&path)?, EnvSource::DotEnv(path));
+            }
+        }
+
+        Ok(vars
+            .into_iter()
+            .map(|(key, (value, source))| EnvVar { key, value, source })
+            .collect())
+    }
+}
+
+/// Reads the variables from a `.env` file without changing loop's own environment.
+pub fn read_dotenv(path: &Path) -> Result<BTreeMap<String, String>, EnvError> {
+    let error = |err: dotenvy::Error| EnvError {
+        path: path.to_path_buf(),
+        message: err.to_string(),
+    };
+    dotenvy::from_path_iter(path)
+        .map_err(error)?
+        .map(|item| item.map_err(error))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    fn map(pairs: &[(&str, &str)]) -> BTreeMap<String, String> {
+        pairs
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_resolve_precedence() {
+        let temp_dir = tempdir().unwrap();
+        let api = temp_dir.path().join("api");
+        fs::create_dir(&api).unwrap();
+        fs::write(api.join(".env"), "FROM_DOTENV=1\nSHARED=dotenv\n").unwrap();
+
+        let mut environment = Environment {
+            global: map(&[("SHARED", "global"), ("GLOBAL", "1")]),
+            groups: BTreeMap::from([
+                ("backend".to_string(), map(&[("SHARED", "backend")])),
+                ("web".to_string(), map(&[("WEB", "1")])),
+            ]),
+            directories: BTreeMap::from([("api".to_string(), map(&[("SHARED", "api")]))]),
+            group_members: BTreeMap::from([
+                ("backend".to_string(), vec!["api".to_string()]),
+                ("web".to_string(), vec!["site".to_string()]),
+            ]),
+            dotenv: false,
+        };
+
+        let vars = environment.resolve(&api).unwrap();
+        assert_eq!(
+            vars,
+            vec![
+                EnvVar {
+                    key: "GLOBAL".to_string(),
+                    value: "1".to_string(),
+                    source: EnvSource::Config,
+                },
+                EnvVar {
+                    key: "SHARED".to_string(),
+                    value: "api".to_string(),
+                    source: EnvSource::Directory,
+                },
+            ]
+        );
+
+        environment.dotenv = true;
+        let vars = environment.resolve(&api).unwrap();
+        let shared = vars.iter().find(|var| var.key == "SHARED").unwrap();
+        assert_eq!(shared.value, "dotenv");
+        assert_eq!(shared.source, EnvSource::DotEnv(api.join(".env")));
+        assert!(vars.iter().any(|var| var.key == "FROM_DOTENV"));
+
+        let site = temp_dir.path().join("site");
+        let vars = environment.resolve(&site).unwrap();
+        assert_eq!(
+            vars.iter().map(|var| var.key.as_str()).collect::<Vec<_>>(),
+            vec!["GLOBAL", "SHARED", "WEB"]
+        );
+    }
+
+    #[test]
+    fn test_read_dotenv_reports_errors() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().join(".env");
+        fs::write(
+            &path,
+            "# comment\nQUOTED=\"two words\"\nexport EXPORTED=yes\n",
+        )
+        .unwrap();
+        assert_eq!(
+            read_dotenv(&path).unwrap(),
+            map(&[("QUOTED", "two words"), ("EXPORTED", "yes")])
+        );
+
+        fs::write(&path, "NOT A VALID LINE\n").unwrap();
+        let err = read_dotenv(&path).unwrap_err();
+        assert_eq!(err.path, path);
+    }
+}
@@ -0,0 +1,171 @@
+use crate::config::{ConfigError, InitError};
+use crate::env::EnvError;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+/// The ways loop itself can fail, as opposed to the commands it runs.
+///
+/// Library functions return these instead of panicking or exiting, so that loop can be
+/// embedded in other tools. `exit_code` gives the status the `loop` binary exits with.
+#[derive(Debug)]
+pub enum LoopError {
+    /// The configuration file could not be read or is invalid.
+    Config(ConfigError),
+    /// `--init` could not create the configuration file.
+    Init(InitError),
+    /// A directory's `.env` file could not be read.
+    Env(EnvError),
+    /// `--group` named a group that is not configured.
+    UnknownGroup(String),
+    /// Options built with [`LoopOptionsBuilder`](crate::args::LoopOptionsBuilder) are
+    /// contradictory or incomplete.
+    InvalidOptions(String),
+    /// `--pipeline` named a pipeline that is not configured.
+    UnknownPipeline(String),
+    /// `loop config get` named a key that does not exist.
+    UnknownKey(String),
+    /// `loop config set` was given a value the key cannot hold.
+    InvalidValue { key: String, source: ConfigError },
+    /// A directory filter pattern is not a valid regular expression.
+    InvalidPattern {
+        flag: &'static str,
+        source: regex::Error,
+    },
+    /// The file given to `--script` does not exist.
+    ScriptNotFound(PathBuf),
+    /// A workspace manifest read by a directory source is invalid.
+    Workspace { path: PathBuf, message: String },
+    /// A command could not be started.
+    Spawn { program: String, source: io::Error },
+    /// Any other I/O error, with a description of what loop was doing.
+    Io { context: String, source: io::Error },
+}
+
+impl LoopError {
+    /// Creates an `Io` error from a description of what failed.
+    pub fn io(context: impl Into<String>, source: io::Error) -> LoopError {
+        LoopError::Io {
+            context: context.into(),
+            source,
+        }
+    }
+
+    /// The exit status for the error, from the `exitcode` conventions.
+    ///
+    /// A command that cannot be started gives 127, as in a shell.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            LoopError::Config(_)
+            | LoopError::InvalidValue { .. }
+            | LoopError::Env(_)
+            | LoopError::Workspace { .. } => exitcode::CONFIG,
+            LoopError::Init(InitError::AlreadyExists(_) | InitError::Shadowed { .. }) => {
+                exitcode::CANTCREAT
+            }
+            LoopError::Init(InitError::Serialize(_)) => exitcode::SOFTWARE,
+            LoopError::Init(InitError::Io(..)) | LoopError::Io { .. } => exitcode::IOERR,
+            LoopError::UnknownGroup(_)
+            | LoopError::InvalidOptions(_)
+            | LoopError::UnknownPipeline(_)
+            | LoopError::UnknownKey(_)
+            | LoopError::InvalidPattern { .. } => exitcode::USAGE,
+            LoopError::ScriptNotFound(_) => exitcode::NOINPUT,
+            LoopError::Spawn { .. } => 127,
+        }
+    }
+}
+
+impl fmt::Display for LoopError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoopError::Config(err) => write!(f, "Invalid configuration: {}", err),
+            LoopError::Init(err) => write!(f, "{}", err),
+            LoopError::Env(err) => write!(f, "Invalid .env file: {}", err),
+            LoopError::UnknownGroup(group) => write!(f, "Unknown group: {}", group),
+            LoopError::InvalidOptions(message) => write!(f, "Invalid options: {}", message),
+            LoopError::UnknownPipeline(name) => write!(f, "Unknown pipeline: {}", name),
+            LoopError::UnknownKey(key) => write!(f, "Unknown configuration key: {}", key),
+            LoopError::InvalidValue { key, source } => {
+                write!(f, "Failed to set {}: {}", key, source.message)
+            }
+            LoopError::InvalidPattern { flag, source } => {
+                write!(f, "Invalid {} pattern: {}", flag, source)
+            }
+            LoopError::ScriptNotFound(path) => write!(f, "Script not found: {}", path.display()),
+            LoopError::Workspace { path, message } => {
+                write!(
+                    f,
+                    "Invalid workspace manifest {}: {}",
+                    path.display(),
+                    message
+                )
+            }
+            LoopError::Spawn { program, source } if program.is_empty() => {
+                write!(f, "{}", source)
+            }
+            LoopError::Spawn { program, source } => {
+                write!(f, "failed to execute {}: {}", program, source)
+            }
+            LoopError::Io { context, source } => write!(f, "{}: {}", context, source),
+        }
+    }
+}
+
+impl std::error::Error for LoopError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            LoopError::Config(err) | LoopError::InvalidValue { source: err, .. } => Some(err),
+            LoopError::Init(err) => Some(err),
+            LoopError::Env(err) => Some(err),
+            LoopError::InvalidPattern { source, .. } => Some(source),
+            LoopError::Spawn { source, .. } | LoopError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<ConfigError> for LoopError {
+    fn from(err: ConfigError) -> LoopError {
+        LoopError::Config(err)
+    }
+}
+
+impl From<InitError> for LoopError {
+    fn from(err: InitError) -> LoopError {
+        LoopError::Init(err)
+    }
+}
+
+impl From<EnvError> for LoopError {
+    fn from(err: EnvError) -> LoopError {
+        LoopError::Env(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exit_codes() {
+        assert_eq!(
+            LoopError::UnknownGroup("web".to_string()).exit_code(),
+            exitcode::USAGE
+        );
+        assert_eq!(
+            LoopError::Init(InitError::AlreadyExists(PathBuf::from(".looprc"))).exit_code(),
+            exitcode::CANTCREAT
+        );
+        assert_eq!(
+            LoopError::ScriptNotFound(PathBuf::from("update.sh")).exit_code(),
+            exitcode::NOINPUT
+        );
+        let spawn = LoopError::Spawn {
+            program: "missing".to_string(),
+            source: io::Error::from(io::ErrorKind::NotFound),
+        };
+        assert_eq!(spawn.exit_code(), 127);
+        assert!(spawn.to_string().starts_with("failed to execute missing: "));
+    }
+}
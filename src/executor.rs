@@ -1,9 +1,20 @@
 use crate::args::LoopOptions;
-use crate::config::LoopConfig;
+use crate::config::{ExitPolicy, LoopConfig, PipelineStep};
+use crate::env::Environment;
+use crate::error::LoopError;
+use crate::observer::{ConsoleObserver, Event, Observer};
+use crate::output::{self, OutputMode};
+use crate::report::{CapturedOutput, DirOutcome, SkipReason, Status, StepOutcome};
+use crate::shell::{self, ShellKind};
+use crate::signals;
+use crate::template::{self, TemplateContext};
 use regex::Regex;
-use std::io::{self, Write};
-use std::path::Path;
-use std::process::{Command, Stdio};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus, Stdio};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 /// Determines if a directory should be processed based on the provided options and configuration.
 ///
@@ -14,137 +25,697 @@ pub fn should_process_directory(
     options: &LoopOptions,
     config: &LoopConfig,
 ) -> bool {
-    let dir_name = dir_path.file_name().unwrap_or_default().to_str().unwrap();
+    skip_reason(dir_path, options, config).is_none()
+}
+
+/// Returns why a directory is filtered out by the options and configuration, or `None`
+/// if it should be processed.
+pub fn skip_reason(
+    dir_path: &Path,
+    options: &LoopOptions,
+    config: &LoopConfig,
+) -> Option<SkipReason> {
+    let dir_name = dir_path.file_name().unwrap_or_default().to_string_lossy();
+    let dir_name = dir_name.as_ref();
 
     if let Some(ref include_only) = options.include_only {
-        return include_only.contains(&dir_name.to_string())
-            || include_only.contains(&".".to_string());
+        let included =
+            include_only.contains(&dir_name.to_string()) || include_only.contains(&".".to_string());
+        return (!included).then_some(SkipReason::NotIncluded);
     }
 
     if let Some(ref exclude_only) = options.exclude_only {
-        return !exclude_only.contains(&dir_name.to_string());
+        return exclude_only
+            .contains(&dir_name.to_string())
+            .then_some(SkipReason::Excluded);
     }
 
     if let Some(ref include) = options.include {
         if include.contains(&dir_name.to_string()) {
-            return true;
+            return None;
         }
     }
 
     if let Some(ref exclude) = options.exclude {
         if exclude.contains(&dir_name.to_string()) {
-            return false;
+            return Some(SkipReason::Excluded);
+        }
+    }
+
+    if let Some(ref groups) = options.group {
+        let in_group = groups
+            .iter()
+            .filter_map(|group| config.groups.get(group))
+            .flatten()
+            .any(|member| member == dir_name);
+        if !in_group {
+            return Some(SkipReason::NotInGroup);
         }
     }
 
     if let Some(ref include_pattern) = options.include_pattern {
-        let re = Regex::new(include_pattern).unwrap();
-        if !re.is_match(dir_name) {
-            return false;
+        if !Regex::new(include_pattern).is_ok_and(|re| re.is_match(dir_name)) {
+            return Some(SkipReason::NotMatched);
         }
     }
 
     if let Some(ref exclude_pattern) = options.exclude_pattern {
-        let re = Regex::new(exclude_pattern).unwrap();
-        if re.is_match(dir_name) {
-            return false;
+        if Regex::new(exclude_pattern).is_ok_and(|re| re.is_match(dir_name)) {
+            return Some(SkipReason::Excluded);
         }
     }
 
     if config.ignore.contains(&dir_name.to_string()) {
-        return false;
+        return Some(SkipReason::Ignored);
     }
 
-    true
+    None
 }
 
-/// Executes the specified command in the given directory.
+/// Settings that control how commands are spawned in each directory.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ExecSettings {
+    /// The shell to run commands with. Defaults to `$SHELL`, then `/bin/sh`.
+    pub shell: Option<String>,
+    /// Spawn the command's argv directly, without a shell.
+    pub no_shell: bool,
+    /// Skip sourcing the shell's rc file before running the command.
+    pub no_rc: bool,
+    /// Command aliases from the configuration.
+    pub aliases: BTreeMap<String, String>,
+    /// Placeholder values from the configuration.
+    pub variables: BTreeMap<String, String>,
+    /// The root directory of the run, exported as `LOOP_ROOT`. `{path}` is relative
+    /// to it. Defaults to the current directory.
+    pub root: Option<PathBuf>,
+    /// An identifier shared by every command in the run, exported as `LOOP_RUN_ID`.
+    pub run_id: String,
+    /// The environment variables configured for each directory.
+    pub env: Environment,
+    /// A script file to run in each directory instead of the command.
+    pub script: Option<PathBuf>,
+    /// What happens to the output of each command.
+    pub output: OutputMode,
+    /// The signals that interrupt the run.
+    pub interrupts: signals::Interrupts,
+}
+
+impl ExecSettings {
+    /// Builds the execution settings from the command-line options and configuration.
+    ///
+    /// `--rc` and `--no-rc` take precedence over the `no_rc` configuration setting.
+    /// `root` is the directory the run operates on; a relative `--script` path is
+    /// resolved against it.
+    pub fn from_options(options: &LoopOptions, config: &LoopConfig, root: &Path) -> ExecSettings {
+        ExecSettings {
+            shell: options.shell.clone(),
+            no_shell: options.no_shell,
+            no_rc: !options.rc && (options.no_rc || config.no_rc),
+            aliases: config.aliases.clone(),
+            variables: config.variables.clone(),
+            root: Some(root.to_path_buf()),
+            run_id: new_run_id(),
+            env: Environment::from_config(config, options.dotenv),
+            script: options
+                .script
+                .as_deref()
+                .map(|script| resolve_script(script, config, root)),
+            output: options.output,
+            interrupts: signals::Interrupts::start(),
+        }
+    }
+
+    /// The root directory of the run, or `.` if none is set.
+    pub fn root(&self) -> &Path {
+        self.root.as_deref().unwrap_or(Path::new("."))
+    }
+
+    /// The shell commands are run with when `no_shell` is not set.
+    pub fn resolve_shell(&self) -> String {
+        self.shell
+            .clone()
+            .or_else(|| std::env::var("SHELL").ok())
+            .unwrap_or_else(|| "/bin/sh".to_string())
+    }
+
+    /// The kind of shell commands are run with, or `None` when `no_shell` is set.
+    pub fn shell_kind(&self) -> Option<ShellKind> {
+        (!self.no_shell).then(|| ShellKind::from_shell(&self.resolve_shell()))
+    }
+}
+
+/// Finds the script file for `--script`.
 ///
-/// This function runs the provided command in the specified directory, handling
-/// different shell configurations and providing appropriate output based on the
-/// command's success or failure.
-pub fn execute_command_in_directory(dir: &Path, command: &[String]) -> i32 {
-    let shell = std::env::var("SHELL").unwrap_or_else(|_| "/bin/sh".to_string());
-    let command_str = command.join(" ");
-
-    let script = if shell.ends_with("zsh") {
-        format!(
-            r#"
-            source ~/.zshrc 2>/dev/null
-            eval "{}"
-            "#,
-            command_str.replace('"', r#"\""#)
-        )
-    } else if shell.ends_with("fish") {
-        format!(
-            r#"
-            source ~/.config/fish/config.fish 2>/dev/null
-            {}
-            "#,
-            command_str
-        )
-    } else {
-        // Assume bash-like shell
-        format!(
-            r#"
-            if [ -f ~/.bashrc ]; then . ~/.bashrc; fi
-            {}
-            "#,
-            command_str
-        )
+/// A name from the `scripts` section of the configuration is replaced by its path. The
+/// path is resolved against the root of the run and made absolute, so that it still
+/// refers to the same file when the script runs inside each directory.
+pub fn resolve_script(script: &str, config: &LoopConfig, root: &Path) -> PathBuf {
+    let path = root.join(
+        config
+            .scripts
+            .get(script)
+            .map(String::as_str)
+            .unwrap_or(script),
+    );
+    std::path::absolute(&path).unwrap_or(path)
+}
+
+/// How a command run in a directory ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    /// The command exited with the given code. 0 means it succeeded.
+    Exited(i32),
+    /// The command was terminated by the given signal.
+    Signaled(i32),
+}
+
+impl Outcome {
+    /// Converts the exit status of a finished process.
+    pub fn from_status(status: ExitStatus) -> Outcome {
+        #[cfg(unix)]
+        if let Some(signal) = std::os::unix::process::ExitStatusExt::signal(&status) {
+            return Outcome::Signaled(signal);
+        }
+        Outcome::Exited(status.code().unwrap_or(1))
+    }
+
+    /// Whether the command exited with code 0.
+    pub fn success(self) -> bool {
+        self == Outcome::Exited(0)
+    }
+
+    /// The exit code a shell would report for the command: its own exit code, or 128
+    /// plus the signal number if it was killed by a signal.
+    pub fn exit_code(self) -> i32 {
+        match self {
+            Outcome::Exited(code) => code,
+            Outcome::Signaled(signal) => signals::exit_code(signal),
+        }
+    }
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Outcome::Exited(code) => write!(f, "exited code {}", code),
+            Outcome::Signaled(signal) => write!(f, "killed by {}", signals::name(*signal)),
+        }
+    }
+}
+
+/// Combines the outcomes of a run into loop's exit code, according to the policy.
+///
+/// With `First` and `Last`, the exit code of the first or last failed directory is
+/// used; with `Max`, the highest exit code. `Count` gives the number of failed
+/// directories, at most 255. A run in which every directory succeeded exits with 0.
+pub fn exit_status(outcomes: &[Outcome], policy: ExitPolicy) -> i32 {
+    let mut failures = outcomes
+        .iter()
+        .filter(|outcome| !outcome.success())
+        .map(|outcome| outcome.exit_code());
+
+    match policy {
+        ExitPolicy::First => failures.next().unwrap_or(exitcode::OK),
+        ExitPolicy::Last => failures.next_back().unwrap_or(exitcode::OK),
+        ExitPolicy::Max => failures.max().unwrap_or(exitcode::OK),
+        ExitPolicy::Count => failures.count().min(255) as i32,
+    }
+}
+
+/// The position of a directory within a run, used for `{index}` and `{total}`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Iteration {
+    /// The one-based index of the directory.
+    pub index: usize,
+    /// The number of directories in the run.
+    pub total: usize,
+}
+
+impl Default for Iteration {
+    fn default() -> Iteration {
+        Iteration { index: 1, total: 1 }
+    }
+}
+
+/// Generates an identifier for a run from the current time and process id.
+pub fn new_run_id() -> String {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_nanos())
+        .unwrap_or_default();
+    format!("{:x}-{:x}", nanos, std::process::id())
+}
+
+/// The `LOOP_*` environment variables describing a directory's place in the run.
+///
+/// `LOOP_DIR` is the absolute path of the directory, `LOOP_DIR_NAME` its name,
+/// `LOOP_ROOT` the directory loop was run from, `LOOP_INDEX` and `LOOP_TOTAL` its
+/// one-based position and the number of directories, and `LOOP_RUN_ID` identifies the
+/// run.
+pub fn loop_env(
+    context: &TemplateContext,
+    settings: &ExecSettings,
+    iteration: Iteration,
+) -> Vec<(&'static str, String)> {
+    let root = settings.root();
+    let root = root
+        .canonicalize()
+        .or_else(|_| std::path::absolute(root))
+        .unwrap_or_else(|_| root.to_path_buf());
+
+    vec![
+        (
+            "LOOP_DIR",
+            context.get("abspath").unwrap_or_default().to_string(),
+        ),
+        (
+            "LOOP_DIR_NAME",
+            context.get("name").unwrap_or_default().to_string(),
+        ),
+        ("LOOP_ROOT", root.to_string_lossy().into_owned()),
+        ("LOOP_INDEX", iteration.index.to_string()),
+        ("LOOP_TOTAL", iteration.total.to_string()),
+        ("LOOP_RUN_ID", settings.run_id.clone()),
+    ]
+}
+
+/// Runs commands in directories on behalf of a loop.
+///
+/// `execute_loop` uses [`ShellExecutor`], which spawns local processes. Other
+/// implementations can run commands some other way, such as in a container, or record
+/// them without running anything in tests. An executor only runs the command; its
+/// status is reported to the loop's observer.
+pub trait Executor {
+    /// Runs a command in a directory and waits for it to finish.
+    ///
+    /// `command` is the command as given to loop, before placeholders and aliases are
+    /// resolved. An executor that reads the command's output reports each line to the
+    /// observer as an `Event::OutputLine`. Returns how the command ended, or an error if
+    /// it could not be run.
+    fn execute(
+        &self,
+        dir: &Path,
+        command: &[String],
+        settings: &ExecSettings,
+        iteration: Iteration,
+        observer: &dyn Observer,
+    ) -> Result<Outcome, LoopError>;
+
+    /// Runs a command like `execute`, also returning what it printed when
+    /// `settings.output` captures it.
+    ///
+    /// The default implementation calls `execute` and captures nothing. Executors that
+    /// can capture output override it, keeping each stream within the output mode's
+    /// limit.
+    fn execute_captured(
+        &self,
+        dir: &Path,
+        command: &[String],
+        settings: &ExecSettings,
+        iteration: Iteration,
+        observer: &dyn Observer,
+    ) -> (Result<Outcome, LoopError>, Option<CapturedOutput>) {
+        let result = self.execute(dir, command, settings, iteration, observer);
+        (result, None)
+    }
+}
+
+/// The default executor, which runs each command as a local process.
+///
+/// Placeholders and aliases are resolved by `prepare_command` first. With `no_shell`
+/// the first element of the command is spawned as the program and the rest are passed
+/// as its arguments, untouched. Otherwise the command is run through the configured
+/// shell. If the process cannot be started, a `LoopError::Spawn` is returned.
+///
+/// With a `script`, the script file is run with the shell instead, and the command's
+/// elements are passed to it as arguments; with `no_shell` as well, the script is
+/// executed directly and must be executable.
+///
+/// The command inherits loop's environment, overridden by the variables configured for
+/// the directory and then by the `LOOP_*` variables. If the directory's `.env` file
+/// cannot be read, the command is not run and a `LoopError::Env` is returned.
+///
+/// The command's output is handled according to `settings.output`. When it is captured,
+/// the command's stdout and stderr are pipes rather than loop's terminal.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ShellExecutor;
+
+impl Executor for ShellExecutor {
+    fn execute(
+        &self,
+        dir: &Path,
+        command: &[String],
+        settings: &ExecSettings,
+        iteration: Iteration,
+        observer: &dyn Observer,
+    ) -> Result<Outcome, LoopError> {
+        self.execute_captured(dir, command, settings, iteration, observer)
+            .0
+    }
+
+    fn execute_captured(
+        &self,
+        dir: &Path,
+        command: &[String],
+        settings: &ExecSettings,
+        iteration: Iteration,
+        observer: &dyn Observer,
+    ) -> (Result<Outcome, LoopError>, Option<CapturedOutput>) {
+        let mut captured = settings.output.limit().map(|_| CapturedOutput::default());
+        let result = spawn_and_wait(
+            dir,
+            command,
+            settings,
+            iteration,
+            observer,
+            captured.as_mut(),
+        );
+        (result, captured)
+    }
+}
+
+fn spawn_and_wait(
+    dir: &Path,
+    command: &[String],
+    settings: &ExecSettings,
+    iteration: Iteration,
+    observer: &dyn Observer,
+    captured: Option<&mut CapturedOutput>,
+) -> Result<Outcome, LoopError> {
+    let mut process = build_command(dir, command, settings, iteration)?;
+    let program = process.get_program().to_string_lossy().into_owned();
+    let spawn_error = |source| LoopError::Spawn {
+        program: program.clone(),
+        source,
     };
 
-    println!();
+    let (stdout, stderr) = match settings.output {
+        OutputMode::Inherit => (Stdio::inherit(), Stdio::inherit()),
+        OutputMode::Capture { .. } | OutputMode::Tee { .. } => (Stdio::piped(), Stdio::piped()),
+        OutputMode::Discard => (Stdio::null(), Stdio::null()),
+    };
+    let own_group = signals::prepare(&mut process);
+    let mut child = process
+        .stdout(stdout)
+        .stderr(stderr)
+        .spawn()
+        .map_err(spawn_error)?;
+    let _guard = signals::track(child.id(), own_group, settings.interrupts);
 
-    let status = Command::new(&shell)
-        .arg("-c")
-        .arg(&script)
+    if let Some(captured) = captured {
+        output::collect(&mut child, dir, settings.output, observer, captured);
+    }
+    let status = child.wait().map_err(spawn_error)?;
+    Ok(Outcome::from_status(status))
+}
+
+/// Builds the process that runs a command in a directory, as `ShellExecutor` runs it,
+/// with its arguments, environment and working directory set.
+pub(crate) fn build_command(
+    dir: &Path,
+    command: &[String],
+    settings: &ExecSettings,
+    iteration: Iteration,
+) -> Result<Command, LoopError> {
+    let context = TemplateContext::new(
+        dir,
+        settings.root(),
+        iteration.index,
+        iteration.total,
+        &settings.variables,
+    );
+
+    let env = settings.env.resolve(dir)?;
+
+    let mut process = if let Some(ref script) = settings.script {
+        let args = command
+            .iter()
+            .map(|arg| template::render(arg, &context, |value| value.to_string()));
+        let mut process = if settings.no_shell {
+            Command::new(script)
+        } else {
+            let mut process = Command::new(settings.resolve_shell());
+            process.arg(script);
+            process
+        };
+        process.args(args);
+        process
+    } else if settings.no_shell {
+        let command = prepare_command(command, settings, &context);
+        let Some((program, args)) = command.split_first() else {
+            return Err(LoopError::Spawn {
+                program: String::new(),
+                source: io::Error::new(io::ErrorKind::InvalidInput, "no command to execute"),
+            });
+        };
+        let mut process = Command::new(program);
+        process.args(args);
+        process
+    } else {
+        let command = prepare_command(command, settings, &context);
+        let shell = settings.resolve_shell();
+        let kind = ShellKind::from_shell(&shell);
+        let script = kind.script(&kind.command_line(&command), !settings.no_rc);
+        let mut process = Command::new(&shell);
+        process.arg("-c").arg(script);
+        process
+    };
+
+    process
         .env(
             "HOME",
             std::env::var("HOME").unwrap_or_else(|_| "/home/user".to_string()),
         )
-        .current_dir(dir)
-        .stdout(Stdio::inherit())
-        .stderr(Stdio::inherit())
-        .status()
-        .expect("Failed to execute command");
+        .envs(env.into_iter().map(|var| (var.key, var.value)))
+        .envs(loop_env(&context, settings, iteration))
+        .current_dir(dir);
+    Ok(process)
+}
 
-    let exit_code = status.code().unwrap_or(-1);
-    let dir_name = dir.file_name().unwrap_or_default().to_str().unwrap();
+/// Executes the specified command in the given directory.
+///
+/// This function runs the provided command in the specified directory, handling
+/// different shell configurations and providing appropriate output based on the
+/// command's success or failure.
+pub fn execute_command_in_directory(dir: &Path, command: &[String]) -> Result<Outcome, LoopError> {
+    execute_command_in_directory_with(dir, command, &ExecSettings::default(), Iteration::default())
+}
 
-    if status.success() {
-        println!("\x1b[32m{} ✓\x1b[0m", dir_name);
-    } else {
-        println!("\x1b[31m{} ✗: exited code {}\x1b[0m", dir_name, exit_code);
+/// Executes the specified command in the given directory with the given settings,
+/// using a [`ShellExecutor`] and printing its status with a [`ConsoleObserver`].
+pub fn execute_command_in_directory_with(
+    dir: &Path,
+    command: &[String],
+    settings: &ExecSettings,
+    iteration: Iteration,
+) -> Result<Outcome, LoopError> {
+    execute_in_directory(
+        &ShellExecutor,
+        &ConsoleObserver,
+        dir,
+        command,
+        settings,
+        iteration,
+    )
+    .into_result()
+}
+
+/// Executes a command in the given directory with an executor.
+///
+/// The observer is notified when the command starts and finishes. Returns the outcome of
+/// the command, or the error that prevented it from running.
+pub fn execute_in_directory(
+    executor: &dyn Executor,
+    observer: &dyn Observer,
+    dir: &Path,
+    command: &[String],
+    settings: &ExecSettings,
+    iteration: Iteration,
+) -> DirOutcome {
+    observer.on_event(&Event::DirectoryStarted {
+        path: dir,
+        iteration,
+    });
+    let started = Instant::now();
+    let (result, output) = executor.execute_captured(dir, command, settings, iteration, observer);
+    let mut outcome = DirOutcome::finished(dir.to_path_buf(), result, started.elapsed());
+    outcome.output = output;
+    observer.on_event(&Event::DirectoryFinished { outcome: &outcome });
+    outcome
+}
+
+/// Executes the steps of a pipeline one after another in the given directory, with an
+/// executor.
+///
+/// Each step is run as a single-element command, so it is shell code. When a step
+/// fails, the remaining steps are skipped, unless the step is marked
+/// `continue_on_error`. A step that could not be run stops the pipeline like a failed
+/// one. No further steps are started once loop has received SIGINT or SIGTERM.
+///
+/// The observer is notified of the directory and of every step, including the skipped
+/// ones. The returned outcome records every step; its own outcome or error is that of
+/// the step that stopped the pipeline, or success.
+pub fn execute_pipeline_in_directory(
+    executor: &dyn Executor,
+    observer: &dyn Observer,
+    dir: &Path,
+    steps: &[PipelineStep],
+    settings: &ExecSettings,
+    iteration: Iteration,
+) -> DirOutcome {
+    observer.on_event(&Event::DirectoryStarted {
+        path: dir,
+        iteration,
+    });
+    let started = Instant::now();
+    let mut results = Vec::with_capacity(steps.len());
+    let mut result = Ok(Outcome::Exited(0));
+    let mut output: Option<CapturedOutput> = None;
+
+    let running = |result: &Result<Outcome, LoopError>| result.as_ref().is_ok_and(|o| o.success());
+
+    for (index, step) in steps.iter().enumerate() {
+        if running(&result) {
+            if let Some(signal) = settings.interrupts.received() {
+                result = Ok(Outcome::Signaled(signal));
+            }
+        }
+        if !running(&result) {
+            let skipped = StepOutcome {
+                label: step.label().to_string(),
+                status: Status::Skipped,
+                outcome: None,
+                duration: Duration::ZERO,
+                continue_on_error: step.continue_on_error,
+            };
+            observer.on_event(&Event::StepFinished {
+                path: dir,
+                step: &skipped,
+                error: None,
+            });
+            results.push(skipped);
+            continue;
+        }
+
+        observer.on_event(&Event::StepStarted {
+            path: dir,
+            label: step.label(),
+            index,
+            total: steps.len(),
+        });
+        let step_started = Instant::now();
+        let (step_result, step_output) = executor.execute_captured(
+            dir,
+            std::slice::from_ref(&step.run),
+            settings,
+            iteration,
+            observer,
+        );
+        if let (Some(step_output), Some(limit)) = (step_output, settings.output.limit()) {
+            output
+                .get_or_insert_with(CapturedOutput::default)
+                .append(&step_output, limit);
+        }
+        let outcome = StepOutcome {
+            label: step.label().to_string(),
+            status: match step_result {
+                Ok(outcome) if outcome.success() => Status::Succeeded,
+                _ => Status::Failed,
+            },
+            outcome: step_result.as_ref().ok().copied(),
+            duration: step_started.elapsed(),
+            continue_on_error: step.continue_on_error,
+        };
+        observer.on_event(&Event::StepFinished {
+            path: dir,
+            step: &outcome,
+            error: step_result.as_ref().err(),
+        });
+        if outcome.status == Status::Failed && !step.continue_on_error {
+            result = step_result;
+        }
+        results.push(outcome);
     }
 
-    io::stdout().flush().unwrap();
+    let mut outcome = DirOutcome::finished(dir.to_path_buf(), result, started.elapsed());
+    outcome.steps = results;
+    outcome.output = output;
+    observer.on_event(&Event::DirectoryFinished { outcome: &outcome });
+    outcome
+}
+
+/// Builds the command to run in one directory.
+///
+/// Placeholders are filled in first. A single-element command is shell code, so values
+/// substituted into it are quoted for the shell; in separate arguments they are inserted
+/// as is, since each argument is quoted as a whole later. Then an alias at the start of
+/// the command is expanded: through a shell, the alias is kept intact as shell code and
+/// the remaining arguments are quoted onto it, giving a single-element command; without
+/// a shell, the alias is split into words by shell quoting rules.
+///
+/// Placeholders in shell code are replaced by quoted values wherever they appear, so
+/// they should be written outside quotes: `echo {name}`, not `echo '{name}'`.
+pub fn prepare_command(
+    command: &[String],
+    settings: &ExecSettings,
+    context: &TemplateContext,
+) -> Vec<String> {
+    let raw = |value: &str| value.to_string();
+    let shell = settings.shell_kind();
+
+    let command: Vec<String> = match (shell, command) {
+        (Some(kind), [script]) => {
+            vec![template::render(script, context, |value| kind.quote(value))]
+        }
+        _ => command
+            .iter()
+            .map(|arg| template::render(arg, context, raw))
+            .collect(),
+    };
 
-    exit_code
+    let Some((first, rest)) = command.split_first() else {
+        return command;
+    };
+    let Some(alias) = settings.aliases.get(first) else {
+        return command;
+    };
+
+    match shell {
+        Some(kind) => {
+            let alias = template::render(alias, context, |value| kind.quote(value));
+            if rest.is_empty() {
+                vec![alias]
+            } else {
+                vec![format!("{} {}", alias, kind.quote_args(rest))]
+            }
+        }
+        None => shell::split_words(alias)
+            .iter()
+            .map(|word| template::render(word, context, raw))
+            .chain(rest.iter().cloned())
+            .collect(),
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::config::LoopConfig;
+    use crate::observer::{NoopObserver, Stream};
     use std::path::PathBuf;
 
     #[test]
     fn test_should_process_directory() {
         let args = LoopOptions {
             command: vec!["test".to_string()],
-            cwd: None,
             include: Some(vec!["include_dir".to_string()]),
             exclude: Some(vec!["exclude_dir".to_string()]),
-            include_only: None,
-            exclude_only: None,
-            include_pattern: None,
-            exclude_pattern: None,
-            init: false,
+            ..LoopOptions::default()
         };
 
         let config = LoopConfig {
             ignore: vec![".git".to_string()],
+            ..LoopConfig::default()
         };
 
         assert!(should_process_directory(
@@ -173,18 +744,14 @@ mod tests {
     fn test_should_process_directory_with_patterns() {
         let args = LoopOptions {
             command: vec!["test".to_string()],
-            cwd: None,
-            include: None,
-            exclude: None,
-            include_only: None,
-            exclude_only: None,
             include_pattern: Some("src.*".to_string()),
             exclude_pattern: Some("test.*".to_string()),
-            init: false,
+            ..LoopOptions::default()
         };
 
         let config = LoopConfig {
             ignore: vec!["node_modules".to_string(), "target".to_string()],
+            ..LoopConfig::default()
         };
 
         assert!(should_process_directory(
@@ -209,16 +776,366 @@ mod tests {
         ));
     }
 
+    #[test]
+    fn test_should_process_directory_with_groups() {
+        let mut args = LoopOptions {
+            command: vec!["test".to_string()],
+            group: Some(vec!["rust".to_string()]),
+            ..LoopOptions::default()
+        };
+
+        let mut config = LoopConfig::default();
+        config
+            .groups
+            .insert("rust".to_string(), vec!["api".to_string()]);
+        config
+            .groups
+            .insert("node".to_string(), vec!["web".to_string()]);
+
+        assert!(should_process_directory(
+            &PathBuf::from("api"),
+            &args,
+            &config
+        ));
+        assert!(!should_process_directory(
+            &PathBuf::from("web"),
+            &args,
+            &config
+        ));
+
+        args.group = Some(vec!["rust".to_string(), "node".to_string()]);
+        assert!(should_process_directory(
+            &PathBuf::from("web"),
+            &args,
+            &config
+        ));
+    }
+
     #[test]
     fn test_execute_command_in_directory() {
         let temp_dir = tempfile::tempdir().unwrap();
         let dir_path = temp_dir.path();
 
-        let exit_code =
+        let outcome =
             execute_command_in_directory(dir_path, &["echo".to_string(), "test".to_string()]);
-        assert_eq!(exit_code, 0);
+        assert_eq!(outcome.unwrap(), Outcome::Exited(0));
+
+        let outcome = execute_command_in_directory(dir_path, &["false".to_string()]);
+        assert_eq!(outcome.unwrap(), Outcome::Exited(1));
+    }
+
+    #[test]
+    fn test_prepare_command_aliases() {
+        let mut settings = ExecSettings {
+            shell: Some("/bin/bash".to_string()),
+            ..ExecSettings::default()
+        };
+        settings.aliases.insert(
+            "commit".to_string(),
+            "git commit -m 'wip  thing'".to_string(),
+        );
+        let context = TemplateContext::default();
+
+        assert_eq!(
+            prepare_command(&["commit".to_string()], &settings, &context),
+            vec!["git commit -m 'wip  thing'"]
+        );
+        assert_eq!(
+            prepare_command(
+                &[
+                    "commit".to_string(),
+                    "--author".to_string(),
+                    "A B".to_string()
+                ],
+                &settings,
+                &context
+            ),
+            vec!["git commit -m 'wip  thing' --author 'A B'"]
+        );
+        assert_eq!(
+            prepare_command(&["ls".to_string(), "a b".to_string()], &settings, &context),
+            vec!["ls", "a b"]
+        );
+
+        settings.no_shell = true;
+        assert_eq!(
+            prepare_command(
+                &["commit".to_string(), "-q".to_string()],
+                &settings,
+                &context
+            ),
+            vec!["git", "commit", "-m", "wip  thing", "-q"]
+        );
+    }
+
+    #[test]
+    fn test_prepare_command_placeholders() {
+        let mut settings = ExecSettings {
+            shell: Some("/bin/sh".to_string()),
+            ..ExecSettings::default()
+        };
+        settings
+            .aliases
+            .insert("backup".to_string(), "tar czf ../{name}.tgz .".to_string());
+        let context = TemplateContext::new(
+            Path::new("./my repo"),
+            Path::new("."),
+            1,
+            2,
+            &BTreeMap::new(),
+        );
 
-        let exit_code = execute_command_in_directory(dir_path, &["false".to_string()]);
-        assert_ne!(exit_code, 0);
+        assert_eq!(
+            prepare_command(
+                &["echo {name} {index}/{total}".to_string()],
+                &settings,
+                &context
+            ),
+            vec!["echo 'my repo' 1/2"]
+        );
+        assert_eq!(
+            prepare_command(
+                &["echo".to_string(), "{name}: {path}".to_string()],
+                &settings,
+                &context
+            ),
+            vec!["echo", "my repo: my repo"]
+        );
+        assert_eq!(
+            prepare_command(&["backup".to_string()], &settings, &context),
+            vec!["tar czf ../'my repo'.tgz ."]
+        );
+    }
+
+    #[test]
+    fn test_loop_env() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let dir = temp_dir.path().join("repo");
+        std::fs::create_dir(&dir).unwrap();
+        let settings = ExecSettings {
+            root: Some(temp_dir.path().to_path_buf()),
+            run_id: "run-1".to_string(),
+            ..ExecSettings::default()
+        };
+        let iteration = Iteration { index: 2, total: 3 };
+        let context = TemplateContext::new(&dir, temp_dir.path(), 2, 3, &BTreeMap::new());
+
+        let env: BTreeMap<_, _> = loop_env(&context, &settings, iteration)
+            .into_iter()
+            .collect();
+        assert_eq!(
+            env["LOOP_DIR"],
+            dir.canonicalize().unwrap().to_string_lossy()
+        );
+        assert_eq!(env["LOOP_DIR_NAME"], "repo");
+        assert_eq!(env["LOOP_ROOT"], temp_dir.path().to_string_lossy());
+        assert_eq!(env["LOOP_INDEX"], "2");
+        assert_eq!(env["LOOP_TOTAL"], "3");
+        assert_eq!(env["LOOP_RUN_ID"], "run-1");
+    }
+
+    #[test]
+    fn test_exec_settings_rc_precedence() {
+        let mut options = LoopOptions {
+            command: vec!["test".to_string()],
+            ..LoopOptions::default()
+        };
+        let mut config = LoopConfig::default();
+        assert!(!ExecSettings::from_options(&options, &config, Path::new(".")).no_rc);
+
+        config.no_rc = true;
+        assert!(ExecSettings::from_options(&options, &config, Path::new(".")).no_rc);
+
+        options.rc = true;
+        assert!(!ExecSettings::from_options(&options, &config, Path::new(".")).no_rc);
+
+        options.rc = false;
+        options.no_rc = true;
+        config.no_rc = false;
+        assert!(ExecSettings::from_options(&options, &config, Path::new(".")).no_rc);
+    }
+
+    #[test]
+    fn test_execute_command_without_shell() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let settings = ExecSettings {
+            no_shell: true,
+            ..ExecSettings::default()
+        };
+
+        let outcome = execute_command_in_directory_with(
+            temp_dir.path(),
+            &["sh".to_string(), "-c".to_string(), "exit 3".to_string()],
+            &settings,
+            Iteration::default(),
+        );
+        assert_eq!(outcome.unwrap(), Outcome::Exited(3));
+
+        let outcome = execute_command_in_directory_with(
+            temp_dir.path(),
+            &["definitely-not-a-real-program".to_string()],
+            &settings,
+            Iteration::default(),
+        );
+        let err = outcome.unwrap_err();
+        assert!(matches!(err, LoopError::Spawn { .. }));
+        assert_eq!(err.exit_code(), 127);
+    }
+
+    #[test]
+    fn test_execute_command_with_explicit_shell() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let settings = ExecSettings {
+            shell: Some("/bin/sh".to_string()),
+            ..ExecSettings::default()
+        };
+        assert_eq!(settings.resolve_shell(), "/bin/sh");
+
+        let outcome = execute_command_in_directory_with(
+            temp_dir.path(),
+            &["test \"$0\" = /bin/sh".to_string()],
+            &settings,
+            Iteration::default(),
+        );
+        assert_eq!(outcome.unwrap(), Outcome::Exited(0));
+    }
+
+    #[test]
+    fn test_exit_status_policies() {
+        let outcomes = [
+            Outcome::Exited(0),
+            Outcome::Exited(2),
+            Outcome::Signaled(9),
+            Outcome::Exited(1),
+        ];
+        assert_eq!(exit_status(&outcomes, ExitPolicy::First), 2);
+        assert_eq!(exit_status(&outcomes, ExitPolicy::Last), 1);
+        assert_eq!(exit_status(&outcomes, ExitPolicy::Max), 137);
+        assert_eq!(exit_status(&outcomes, ExitPolicy::Count), 3);
+        for policy in [
+            ExitPolicy::First,
+            ExitPolicy::Last,
+            ExitPolicy::Max,
+            ExitPolicy::Count,
+        ] {
+            assert_eq!(exit_status(&[Outcome::Exited(0)], policy), 0);
+            assert_eq!(exit_status(&[], policy), 0);
+        }
+        assert_eq!(
+            exit_status(&[Outcome::Exited(1); 300], ExitPolicy::Count),
+            255
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_execute_command_killed_by_signal() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let settings = ExecSettings {
+            shell: Some("sh".to_string()),
+            no_rc: true,
+            ..ExecSettings::default()
+        };
+        let outcome = execute_command_in_directory_with(
+            temp_dir.path(),
+            &["kill -9 $$".to_string()],
+            &settings,
+            Iteration::default(),
+        );
+        let outcome = outcome.unwrap();
+        assert_eq!(outcome, Outcome::Signaled(9));
+        assert_eq!(outcome.exit_code(), 137);
+        assert_eq!(outcome.to_string(), "killed by SIGKILL");
+    }
+
+    #[test]
+    fn test_execute_in_directory_captures_output() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mut settings = ExecSettings {
+            shell: Some("sh".to_string()),
+            no_rc: true,
+            output: OutputMode::capture(),
+            ..ExecSettings::default()
+        };
+        let lines = std::sync::Mutex::new(Vec::new());
+        let observer = |event: &Event<'_>| {
+            if let Event::OutputLine { stream, line, .. } = event {
+                lines.lock().unwrap().push((*stream, line.to_string()));
+            }
+        };
+        let command = ["printf 'one\\ntwo\\n'; echo oops >&2; exit 2".to_string()];
+
+        let outcome = execute_in_directory(
+            &ShellExecutor,
+            &observer,
+            temp_dir.path(),
+            &command,
+            &settings,
+            Iteration::default(),
+        );
+        assert_eq!(outcome.exit_code(), Some(2));
+        let output = outcome.output.unwrap();
+        assert_eq!(output.stdout, b"one\ntwo\n");
+        assert_eq!(output.stderr, b"oops\n");
+        assert!(!output.truncated);
+        let mut lines = lines.into_inner().unwrap();
+        lines.sort();
+        assert_eq!(
+            lines,
+            vec![
+                (Stream::Stdout, "one".to_string()),
+                (Stream::Stdout, "two".to_string()),
+                (Stream::Stderr, "oops".to_string()),
+            ]
+        );
+
+        settings.output = OutputMode::Tee { limit: 5 };
+        let outcome = execute_in_directory(
+            &ShellExecutor,
+            &NoopObserver,
+            temp_dir.path(),
+            &command,
+            &settings,
+            Iteration::default(),
+        );
+        let output = outcome.output.unwrap();
+        assert_eq!(output.stdout, b"one\nt");
+        assert!(output.truncated);
+
+        settings.output = OutputMode::Discard;
+        let outcome = execute_in_directory(
+            &ShellExecutor,
+            &NoopObserver,
+            temp_dir.path(),
+            &command,
+            &settings,
+            Iteration::default(),
+        );
+        assert_eq!(outcome.exit_code(), Some(2));
+        assert!(outcome.output.is_none());
+    }
+
+    #[test]
+    fn test_pipeline_captures_output_of_each_step() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let settings = ExecSettings {
+            shell: Some("sh".to_string()),
+            no_rc: true,
+            output: OutputMode::capture(),
+            ..ExecSettings::default()
+        };
+        let steps = [
+            PipelineStep::from_arg("echo build"),
+            PipelineStep::from_arg("echo test"),
+        ];
+        let outcome = execute_pipeline_in_directory(
+            &ShellExecutor,
+            &NoopObserver,
+            temp_dir.path(),
+            &steps,
+            &settings,
+            Iteration::default(),
+        );
+        assert_eq!(outcome.output.unwrap().stdout, b"build\ntest\n");
     }
 }
@@ -1,90 +1,472 @@
+/// Like `println!`, but a stdout that can no longer be written to, such as a closed
+/// pipe, is ignored rather than stopping loop.
+macro_rules! outln {
+    ($($arg:tt)*) => {{
+        use ::std::io::Write as _;
+        let _ = writeln!(::std::io::stdout(), $($arg)*);
+    }};
+}
+
+/// Like `eprintln!`, but ignores errors writing to stderr.
+macro_rules! errln {
+    ($($arg:tt)*) => {{
+        use ::std::io::Write as _;
+        let _ = writeln!(::std::io::stderr(), $($arg)*);
+    }};
+}
+
 pub mod args;
+#[cfg(feature = "async")]
+pub mod async_loop;
 pub mod config;
+pub mod env;
+pub mod error;
 pub mod executor;
+pub mod observer;
+pub mod order;
+pub mod output;
+pub mod report;
+pub mod shell;
+pub mod signals;
+pub mod source;
+pub mod template;
+
+pub use crate::error::LoopError;
 
-use crate::args::LoopOptions;
-use std::path::PathBuf;
-use walkdir::WalkDir;
+use crate::args::{ConfigCommand, LoopOptions};
+use crate::observer::Event;
+use crate::report::{DirOutcome, RunReport, SkipReason};
+use crate::source::DirectorySource;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
 
 /// Runs the main loop command based on the provided options.
 ///
 /// This function is the entry point for the loop command's functionality.
 /// It handles initialization if requested, or executes the loop command
-/// based on the provided options.
-pub fn run(args: LoopOptions) -> i32 {
+/// based on the provided options. The returned exit code reflects the commands that
+/// were run; errors of loop itself, such as an invalid configuration, are returned as
+/// a `LoopError`.
+pub fn run(args: LoopOptions) -> Result<i32, LoopError> {
     if args.init {
-        config::create_looprc();
-        return exitcode::OK;
-    }
-
-    let options = LoopOptions {
-        command: args.command,
-        cwd: args.cwd,
-        include: args.include,
-        exclude: args.exclude,
-        include_only: args.include_only,
-        exclude_only: args.exclude_only,
-        include_pattern: args.include_pattern,
-        exclude_pattern: args.exclude_pattern,
-        init: args.init,
+        let dir = root_dir(&args);
+        let format = args.format.unwrap_or(config::ConfigFormat::Json);
+        config::init_config(&dir, format, args.force)?;
+        return Ok(exitcode::OK);
+    }
+
+    if let Some(ref config_command) = args.config_command {
+        run_config_command(config_command, &args)?;
+        return Ok(exitcode::OK);
+    }
+
+    let record_durations = args.sort == Some(order::SortOrder::Duration);
+    let report = execute_loop(args)?;
+    if record_durations {
+        order::Durations::record(&report);
+    }
+    Ok(report.exit_code())
+}
+
+/// Returns the directory loop runs in: `--cwd` with `~` expanded, or `.`.
+fn root_dir(args: &LoopOptions) -> PathBuf {
+    args.cwd
+        .as_deref()
+        .map(|cwd| PathBuf::from(config::expand_tilde(cwd)))
+        .unwrap_or_else(|| PathBuf::from("."))
+}
+
+/// Runs a `loop config` subcommand.
+///
+/// `validate` returns the first problem in the configuration file as an error, which
+/// the binary reports with its location and `exitcode::CONFIG`, so it can be used as a
+/// CI check. `show` prints every effective setting with its source, while `get` and
+/// `set` read and update single keys of the configuration file. `env` prints the
+/// environment variables set for the commands run in a directory, with the setting
+/// each one comes from.
+pub fn run_config_command(command: &ConfigCommand, args: &LoopOptions) -> Result<(), LoopError> {
+    let dir = root_dir(args);
+    let path = config::find_config_file(&dir);
+    let load = || config::load_config(&dir);
+
+    match command {
+        ConfigCommand::Validate => match path {
+            Some(path) => {
+                config::load_config_file(&path)?;
+                outln!("{}: OK", path.display());
+            }
+            None => outln!("No configuration file found in {}", dir.display()),
+        },
+        ConfigCommand::Show => show_config(&load()?.interpolated(), path.as_deref(), args),
+        ConfigCommand::Get { key } => {
+            let value = config::get_value(&load()?.interpolated(), key)
+                .ok_or_else(|| LoopError::UnknownKey(key.clone()))?;
+            outln!("{}", value);
+        }
+        ConfigCommand::Set { key, values } => {
+            let path = path.unwrap_or_else(|| dir.join(".looprc"));
+            config::set_value(&load()?, key, values)
+                .and_then(|updated| {
+                    let value = config::get_value(&updated, key).unwrap_or(serde_json::Value::Null);
+                    config::update_config_file(&path, key, &value)
+                })
+                .map_err(|source| LoopError::InvalidValue {
+                    key: key.clone(),
+                    source,
+                })?;
+            outln!("Updated {} in {}", key, path.display());
+        }
+        ConfigCommand::Env { dir: target } => {
+            let environment = env::Environment::from_config(&load()?.interpolated(), args.dotenv);
+            let vars = environment.resolve(&dir.join(config::expand_tilde(target)))?;
+            if vars.is_empty() {
+                outln!("No environment variables are set for {}", target);
+            }
+            for var in vars {
+                let assignment = format!("{}={}", var.key, var.value);
+                outln!("{:<40} # {}", assignment, var.source);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Prints the effective configuration file settings and command-line options.
+///
+/// Each line shows the key, its effective value as JSON and where that value comes
+/// from: the configuration file, a command-line flag, or the built-in default. A flag
+/// given on the command line overrides the setting of the same name, and `--rc`
+/// overrides `no_rc`.
+fn show_config(loop_config: &config::LoopConfig, path: Option<&Path>, args: &LoopOptions) {
+    let file_keys = path
+        .and_then(|path| config::file_keys(path).ok())
+        .unwrap_or_default();
+
+    match path {
+        Some(path) => outln!("Configuration file: {}", path.display()),
+        None => outln!("Configuration file: none"),
+    }
+    outln!();
+
+    let mut entries = Vec::new();
+    if let Ok(serde_json::Value::Object(values)) = serde_json::to_value(loop_config) {
+        for (key, value) in values {
+            let source = match path {
+                Some(path) if file_keys.contains(&key) => path.display().to_string(),
+                _ => "default".to_string(),
+            };
+            entries.push((key, value, source));
+        }
+    }
+
+    let options = [
+        ("cwd", "--cwd", serde_json::json!(args.cwd)),
+        ("include", "--include", serde_json::json!(args.include)),
+        ("exclude", "--exclude", serde_json::json!(args.exclude)),
+        ("group", "--group", serde_json::json!(args.group)),
+        ("shell", "--shell", serde_json::json!(args.shell)),
+        ("no_shell", "--no-shell", serde_json::json!(args.no_shell)),
+        ("no_rc", "--no-rc", serde_json::json!(args.no_rc)),
+        ("dotenv", "--dotenv", serde_json::json!(args.dotenv)),
+        ("script", "--script", serde_json::json!(args.script)),
+        ("steps", "--step", serde_json::json!(args.steps)),
+        ("pipeline", "--pipeline", serde_json::json!(args.pipeline)),
+        (
+            "exit_status",
+            "--exit-status",
+            serde_json::json!(args.exit_status),
+        ),
+        (
+            "include_only",
+            "--include-only",
+            serde_json::json!(args.include_only),
+        ),
+        (
+            "exclude_only",
+            "--exclude-only",
+            serde_json::json!(args.exclude_only),
+        ),
+        (
+            "include_pattern",
+            "--include-pattern",
+            serde_json::json!(args.include_pattern),
+        ),
+        (
+            "exclude_pattern",
+            "--exclude-pattern",
+            serde_json::json!(args.exclude_pattern),
+        ),
+        (
+            "allow_invalid_config",
+            "--allow-invalid-config",
+            serde_json::json!(args.allow_invalid_config),
+        ),
+        ("reverse", "--reverse", serde_json::json!(args.reverse)),
+    ];
+    for (key, flag, value) in options {
+        let given = !matches!(
+            value,
+            serde_json::Value::Null | serde_json::Value::Bool(false)
+        );
+        match entries.iter_mut().find(|(name, ..)| name == key) {
+            Some(entry) if given => {
+                *entry = (key.to_string(), value, format!("command line ({})", flag));
+            }
+            Some(_) => {}
+            None if given => {
+                entries.push((key.to_string(), value, format!("command line ({})", flag)));
+            }
+            None => entries.push((key.to_string(), value, "default".to_string())),
+        }
+    }
+    // Without --sort, the effective order is the default one rather than no value.
+    let sort_source = match args.sort {
+        Some(_) => "command line (--sort)",
+        None => "default",
     };
+    entries.push((
+        "sort".to_string(),
+        serde_json::json!(args.sort.unwrap_or_default()),
+        sort_source.to_string(),
+    ));
+    if args.rc {
+        if let Some(entry) = entries.iter_mut().find(|(name, ..)| name == "no_rc") {
+            *entry = (
+                "no_rc".to_string(),
+                serde_json::json!(false),
+                "command line (--rc)".to_string(),
+            );
+        }
+    }
 
-    execute_loop(options)
+    for (key, value, source) in entries {
+        outln!("{:<22} {:<32} {}", key, value.to_string(), source);
+    }
 }
 
 /// Executes the loop command with the given options.
 ///
 /// This function processes directories based on the provided options,
 /// executing the specified command in each relevant directory.
-pub fn execute_loop(options: LoopOptions) -> i32 {
-    let working_dir = options
-        .cwd
-        .clone()
-        .map(PathBuf::from)
-        .unwrap_or_else(|| std::env::current_dir().unwrap());
-    std::env::set_current_dir(working_dir).unwrap();
-
-    let config = config::read_looprc();
-
-    let mut first_error_code: Option<i32> = None;
-
-    // Process child directories
-    for entry in WalkDir::new(".")
-        .min_depth(1)
-        .max_depth(1)
-        .sort_by_file_name()
-    {
-        let entry = entry.unwrap();
-        if entry.file_type().is_dir() {
-            let dir_path = entry.path();
-            if executor::should_process_directory(dir_path, &options, &config) {
-                let exit_code = executor::execute_command_in_directory(dir_path, &options.command);
-                if exit_code != 0 && first_error_code.is_none() {
-                    first_error_code = Some(exit_code);
-                }
-            }
+///
+/// Directories, the configuration file and relative paths are all taken from the root
+/// given by `--cwd`, or the current directory. The process's working directory is never
+/// changed, so several loops can run at the same time in one process.
+///
+/// The returned report has an entry for every directory considered, including those
+/// filtered out. Its exit code combines the exit codes of the directories according to
+/// `--exit-status` or the `exit_status` setting, by default taking the first failure.
+///
+/// Once `signals::install` has been called, as the `loop` binary does, SIGINT and
+/// SIGTERM are handled while the loop runs: they are forwarded to the running command
+/// and no further directories are started. The remaining directories are reported as
+/// skipped, and the report's exit code is 130 or 143, as for a process killed by the
+/// signal. A signal received before the run starts is forgotten.
+///
+/// The status of each directory is printed as it finishes, by a `ConsoleObserver`.
+pub fn execute_loop(options: LoopOptions) -> Result<RunReport, LoopError> {
+    execute_loop_with(
+        options,
+        &executor::ShellExecutor,
+        &observer::ConsoleObserver,
+    )
+}
+
+/// Executes the loop command with the given options, running each command with the
+/// given executor and reporting the progress of the run to the given observer.
+///
+/// Everything else, from reading the configuration to the returned report, is the same
+/// as in `execute_loop`.
+pub fn execute_loop_with(
+    options: LoopOptions,
+    executor: &dyn executor::Executor,
+    observer: &dyn observer::Observer,
+) -> Result<RunReport, LoopError> {
+    let started = Instant::now();
+    let Plan {
+        settings,
+        steps,
+        targets,
+        exit_policy,
+    } = plan(&options, observer)?;
+
+    let total = targets
+        .iter()
+        .filter(|(_, reason)| reason.is_none())
+        .count();
+    let mut directories: Vec<DirOutcome> = Vec::with_capacity(targets.len());
+    let mut index = 0;
+
+    for (dir_path, reason) in targets {
+        let reason = reason.or_else(|| {
+            settings
+                .interrupts
+                .received()
+                .map(|_| SkipReason::Interrupted)
+        });
+        if let Some(reason) = reason {
+            observer.on_event(&Event::DirectorySkipped {
+                path: &dir_path,
+                reason,
+            });
+            directories.push(DirOutcome::skipped(dir_path, reason));
+            continue;
+        }
+        index += 1;
+        let iteration = executor::Iteration { index, total };
+        let outcome = match steps {
+            Some(ref steps) => executor::execute_pipeline_in_directory(
+                executor, observer, &dir_path, steps, &settings, iteration,
+            ),
+            None => executor::execute_in_directory(
+                executor,
+                observer,
+                &dir_path,
+                &options.command,
+                &settings,
+                iteration,
+            ),
+        };
+        directories.push(outcome);
+    }
+
+    let report = RunReport {
+        run_id: settings.run_id.clone(),
+        directories,
+        interrupted: settings.interrupts.received(),
+        exit_policy,
+        duration: started.elapsed(),
+    };
+    observer.on_event(&Event::RunFinished { report: &report });
+    Ok(report)
+}
+
+/// Everything a run needs before the first command starts.
+pub(crate) struct Plan {
+    pub settings: executor::ExecSettings,
+    /// The pipeline steps to run in each directory, if any.
+    pub steps: Option<Vec<config::PipelineStep>>,
+    /// The directories considered, with the reason each filtered one is skipped.
+    pub targets: Vec<(PathBuf, Option<SkipReason>)>,
+    pub exit_policy: config::ExitPolicy,
+}
+
+/// Loads the configuration, checks the options against it and discovers the
+/// directories of a run. The observer is told when the run starts and of every
+/// directory discovered.
+pub(crate) fn plan(
+    options: &LoopOptions,
+    observer: &dyn observer::Observer,
+) -> Result<Plan, LoopError> {
+    let root = root_dir(options);
+
+    let config = match config::load_config(&root) {
+        Ok(config) => config.interpolated(),
+        Err(err) if options.allow_invalid_config => {
+            errln!("Warning: ignoring invalid configuration: {}", err);
+            config::LoopConfig::default()
+        }
+        Err(err) => return Err(err.into()),
+    };
+
+    if let Some(ref groups) = options.group {
+        if let Some(unknown) = groups
+            .iter()
+            .find(|group| !config.groups.contains_key(*group))
+        {
+            return Err(LoopError::UnknownGroup(unknown.clone()));
+        }
+    }
+
+    let patterns = [
+        ("--include-pattern", &options.include_pattern),
+        ("--exclude-pattern", &options.exclude_pattern),
+    ];
+    for (flag, pattern) in patterns {
+        if let Some(pattern) = pattern {
+            regex::Regex::new(pattern)
+                .map_err(|source| LoopError::InvalidPattern { flag, source })?;
+        }
+    }
+
+    let settings = executor::ExecSettings::from_options(options, &config, &root);
+
+    let steps: Option<Vec<config::PipelineStep>> = match (&options.steps, &options.pipeline) {
+        (Some(steps), _) => Some(
+            steps
+                .iter()
+                .map(|step| config::PipelineStep::from_arg(step))
+                .collect(),
+        ),
+        (None, Some(name)) => match config.pipelines.get(name) {
+            Some(steps) => Some(steps.clone()),
+            None => return Err(LoopError::UnknownPipeline(name.clone())),
+        },
+        (None, None) => None,
+    };
+
+    if let Some(ref script) = settings.script {
+        if !script.is_file() {
+            return Err(LoopError::ScriptNotFound(script.clone()));
         }
     }
 
-    // Process included directories
+    observer.on_event(&Event::RunStarted {
+        run_id: &settings.run_id,
+        root: &root,
+    });
+
+    // Directories from the source come first, in its order, followed by included
+    // directories. Filtered directories are kept with the reason they are skipped.
+    // Sources that search the tree do not descend into excluded or ignored
+    // directories. Other filters only apply to the directories themselves, so that
+    // `--include-only` or `--group` can still pick out nested directories.
+    let prune = |dir: &Path| {
+        matches!(
+            executor::skip_reason(dir, options, &config),
+            Some(SkipReason::Excluded | SkipReason::Ignored)
+        )
+    };
+    let directories = match options.source {
+        Some(ref source) => source.pruned_directories(&root, &prune)?,
+        None => source::Children.directories(&root)?,
+    };
+    let mut targets: Vec<(PathBuf, Option<SkipReason>)> = directories
+        .into_iter()
+        .map(|dir| {
+            let reason = if dir.is_dir() {
+                executor::skip_reason(&dir, options, &config)
+            } else {
+                Some(SkipReason::NotADirectory)
+            };
+            (dir, reason)
+        })
+        .collect();
+
     if let Some(ref include_dirs) = options.include {
         for dir in include_dirs {
-            let dir_path = PathBuf::from(dir);
-            if dir_path.is_dir() {
-                let exit_code = executor::execute_command_in_directory(&dir_path, &options.command);
-                if exit_code != 0 && first_error_code.is_none() {
-                    first_error_code = Some(exit_code);
-                }
-            } else {
-                eprintln!("Warning: {} is not a directory", dir);
-            }
+            let dir_path = root.join(config::expand_tilde(dir));
+            let reason = (!dir_path.is_dir()).then_some(SkipReason::NotADirectory);
+            targets.push((dir_path, reason));
         }
     }
 
-    match first_error_code {
-        Some(code) => code,
-        None => exitcode::OK,
+    let mut targets = order::dedupe(targets);
+    order::sort(
+        &mut targets,
+        options.sort.unwrap_or_default(),
+        options.reverse,
+    );
+    for (dir, _) in &targets {
+        observer.on_event(&Event::DirectoryDiscovered { path: dir });
     }
+
+    Ok(Plan {
+        settings,
+        steps,
+        targets,
+        exit_policy: options.exit_status.unwrap_or(config.exit_status),
+    })
 }
 
 #[cfg(test)]
@@ -101,15 +483,90 @@ mod tests {
         let args = LoopOptions {
             command: vec!["echo".to_string(), "test".to_string()],
             cwd: Some(temp_dir.path().to_string_lossy().into_owned()),
-            include: None,
-            exclude: None,
-            include_only: None,
-            exclude_only: None,
-            include_pattern: None,
-            exclude_pattern: None,
-            init: false,
+            ..LoopOptions::default()
         };
-        let exit_code = run(args);
+        let exit_code = run(args).unwrap();
         assert_eq!(exit_code, exitcode::OK);
     }
+
+    #[test]
+    fn test_concurrent_loops_use_their_own_roots() {
+        let roots: Vec<_> = (0..4).map(|_| tempdir().unwrap()).collect();
+        for (index, root) in roots.iter().enumerate() {
+            fs::create_dir(root.path().join(format!("dir{}", index))).unwrap();
+        }
+
+        std::thread::scope(|scope| {
+            for root in &roots {
+                scope.spawn(|| {
+                    let args = LoopOptions {
+                        command: vec!["touch".to_string(), "marker".to_string()],
+                        cwd: Some(root.path().to_string_lossy().into_owned()),
+                        no_shell: true,
+                        ..LoopOptions::default()
+                    };
+                    assert_eq!(run(args).unwrap(), exitcode::OK);
+                });
+            }
+        });
+
+        for (index, root) in roots.iter().enumerate() {
+            assert!(root.path().join(format!("dir{}/marker", index)).exists());
+            assert!(!root.path().join("marker").exists());
+        }
+    }
+
+    #[test]
+    fn test_execute_loop_report() {
+        let temp_dir = tempdir().unwrap();
+        for dir in ["api", "docs", "web"] {
+            fs::create_dir(temp_dir.path().join(dir)).unwrap();
+        }
+        fs::write(temp_dir.path().join("web/fail"), "").unwrap();
+
+        let options = LoopOptions::builder()
+            .cwd(temp_dir.path().to_string_lossy())
+            .steps(["test ! -e fail", "echo unreachable"])
+            .exclude(["docs"])
+            .include(["missing"])
+            .build()
+            .unwrap();
+        let report = execute_loop(options).unwrap();
+
+        let summary: Vec<_> = report
+            .directories
+            .iter()
+            .map(|dir| {
+                (
+                    dir.path.file_name().unwrap().to_string_lossy().into_owned(),
+                    dir.status,
+                    dir.skip_reason,
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("api".to_string(), report::Status::Succeeded, None),
+                (
+                    "docs".to_string(),
+                    report::Status::Skipped,
+                    Some(SkipReason::Excluded)
+                ),
+                ("web".to_string(), report::Status::Failed, None),
+                (
+                    "missing".to_string(),
+                    report::Status::Skipped,
+                    Some(SkipReason::NotADirectory)
+                ),
+            ]
+        );
+
+        let web = &report.directories[2];
+        assert_eq!(web.exit_code(), Some(1));
+        let steps: Vec<_> = web.steps.iter().map(|step| step.status).collect();
+        assert_eq!(steps, vec![report::Status::Failed, report::Status::Skipped]);
+        assert_eq!(report.exit_code(), 1);
+        assert!(report.interrupted.is_none());
+    }
 }
@@ -1,8 +1,17 @@
 use loop_lib::args::parse_args;
-use loop_lib::run;
+use loop_lib::{run, signals};
+use std::io::Write;
 
 fn main() -> std::process::ExitCode {
     let args = parse_args();
-    let exit_code = run(args);
-    std::process::ExitCode::from(exit_code as u8)
+    signals::install();
+    let exit_code = run(args).unwrap_or_else(|err| {
+        // Like `eprintln!`, but a closed stderr does not stop loop from exiting with
+        // the error's code.
+        let _ = writeln!(std::io::stderr(), "{}", err);
+        err.exit_code()
+    });
+    // Exit codes outside 0-255 cannot be reported; use 255 rather than letting them
+    // wrap around, possibly to 0.
+    std::process::ExitCode::from(u8::try_from(exit_code).unwrap_or(u8::MAX))
 }
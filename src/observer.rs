@@ -0,0 +1,244 @@
+use crate::error::LoopError;
+use crate::executor::Iteration;
+use crate::report::{DirOutcome, RunReport, SkipReason, Status, StepOutcome};
+use crate::signals;
+use std::io::{self, Write};
+use std::path::Path;
+
+/// The stream a line of output was written to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Stream {
+    Stdout,
+    Stderr,
+}
+
+/// Something that happened during a run, in the order it happened.
+#[derive(Debug)]
+pub enum Event<'a> {
+    /// The configuration was loaded and directories are about to be discovered.
+    RunStarted { run_id: &'a str, root: &'a Path },
+    /// A directory was planned for the run. Sent once for each directory, in run
+    /// order, after pruning and deduplication, including directories that the filters
+    /// will skip. Directories pruned during discovery are never reported.
+    DirectoryDiscovered { path: &'a Path },
+    /// A directory will not be run.
+    DirectorySkipped { path: &'a Path, reason: SkipReason },
+    /// The command is about to run in a directory.
+    DirectoryStarted {
+        path: &'a Path,
+        iteration: Iteration,
+    },
+    /// A step of a pipeline is about to run. `index` is zero-based.
+    StepStarted {
+        path: &'a Path,
+        label: &'a str,
+        index: usize,
+        total: usize,
+    },
+    /// A step of a pipeline finished, failed to start or was skipped.
+    StepFinished {
+        path: &'a Path,
+        step: &'a StepOutcome,
+        error: Option<&'a LoopError>,
+    },
+    /// A command printed a line. Executors that read the command's output report each
+    /// line; commands writing directly to loop's terminal produce none.
+    OutputLine {
+        path: &'a Path,
+        stream: Stream,
+        line: &'a str,
+    },
+    /// The command finished in a directory, or could not be run.
+    DirectoryFinished { outcome: &'a DirOutcome },
+    /// Every directory has been run, or the run was interrupted.
+    RunFinished { report: &'a RunReport },
+}
+
+/// Receives the events of a run, for example to drive a user interface.
+///
+/// Events are delivered synchronously from the thread running the loop, so an observer
+/// should return quickly. Closures taking an `&Event` are observers too.
+pub trait Observer {
+    fn on_event(&self, event: &Event<'_>);
+}
+
+impl<F: Fn(&Event<'_>)> Observer for F {
+    fn on_event(&self, event: &Event<'_>) {
+        self(event)
+    }
+}
+
+/// An observer that ignores every event.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoopObserver;
+
+impl Observer for NoopObserver {
+    fn on_event(&self, _event: &Event<'_>) {}
+}
+
+/// The observer used by the `loop` command, which prints the status of each directory.
+///
+/// A directory that succeeded is printed in green with ✓, one that failed in red with ✗
+/// and the reason. Pipeline steps are printed as `name › step`, followed by a summary
+/// line for the directory. Output lines are printed to the stream they came from.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ConsoleObserver;
+
+impl Observer for ConsoleObserver {
+    fn on_event(&self, event: &Event<'_>) {
+        match *event {
+            Event::RunStarted { .. } | Event::DirectoryDiscovered { .. } => {}
+            Event::DirectorySkipped {
+                path,
+                reason: SkipReason::NotADirectory,
+            } => errln!("Warning: {} is not a directory", path.display()),
+            Event::DirectorySkipped { .. } => {}
+            Event::DirectoryStarted { .. } => outln!(),
+            Event::StepStarted { index, .. } => {
+                // The first step follows the blank line printed for the directory
+                if index > 0 {
+                    outln!();
+                }
+            }
+            Event::StepFinished { path, step, error } => {
+                let label = format!("{} › {}", dir_name(path), step.label);
+                match (step.status, step.outcome, error) {
+                    (Status::Skipped, ..) => outln!("\x1b[33m{} skipped\x1b[0m", label),
+                    (Status::Succeeded, ..) => outln!("\x1b[32m{} ✓\x1b[0m", label),
+                    (Status::Failed, _, Some(err)) => print_error(&label, err),
+                    (Status::Failed, Some(outcome), None) => {
+                        outln!("\x1b[31m{} ✗: {}\x1b[0m", label, outcome)
+                    }
+                    (Status::Failed, None, None) => outln!("\x1b[31m{} ✗\x1b[0m", label),
+                }
+            }
+            Event::OutputLine {
+                stream: Stream::Stdout,
+                line,
+                ..
+            } => outln!("{}", line),
+            Event::OutputLine {
+                stream: Stream::Stderr,
+                line,
+                ..
+            } => errln!("{}", line),
+            Event::DirectoryFinished { outcome } if outcome.steps.is_empty() => {
+                let name = dir_name(&outcome.path);
+                match (&outcome.error, outcome.outcome) {
+                    (Some(err), _) => print_error(&name, err),
+                    (None, Some(result)) if result.success() => {
+                        outln!("\x1b[32m{} ✓\x1b[0m", name)
+                    }
+                    (None, Some(result)) => outln!("\x1b[31m{} ✗: {}\x1b[0m", name, result),
+                    (None, None) => {}
+                }
+            }
+            Event::DirectoryFinished { outcome } => print_pipeline_summary(outcome),
+            Event::RunFinished { report } => {
+                if let Some(signal) = report.interrupted {
+                    let ran = report.ran().count();
+                    let interrupted = report
+                        .skipped()
+                        .filter(|dir| dir.skip_reason == Some(SkipReason::Interrupted))
+                        .count();
+                    errln!();
+                    errln!(
+                        "Interrupted by {}: {} of {} directories run, {} failed",
+                        signals::name(signal),
+                        ran,
+                        ran + interrupted,
+                        report.failed().count()
+                    );
+                }
+            }
+        }
+        let _ = io::stdout().flush();
+    }
+}
+
+fn dir_name(path: &Path) -> String {
+    path.file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// Prints an error that prevented a command from running. Problems with a `.env` file
+/// are printed without the generic prefix, as the label already names the directory.
+fn print_error(label: &str, err: &LoopError) {
+    match err {
+        LoopError::Env(err) => outln!("\x1b[31m{} ✗: {}\x1b[0m", label, err),
+        err => outln!("\x1b[31m{} ✗: {}\x1b[0m", label, err),
+    }
+}
+
+/// Prints the summary line of a pipeline: the step that stopped it, or the number of
+/// steps run and of failures ignored.
+fn print_pipeline_summary(outcome: &DirOutcome) {
+    let name = dir_name(&outcome.path);
+    let steps = &outcome.steps;
+
+    if outcome.status == Status::Succeeded {
+        let ignored = steps
+            .iter()
+            .filter(|step| step.status == Status::Failed)
+            .count();
+        match ignored {
+            0 => outln!("\x1b[32m{} ✓: {} steps\x1b[0m", name, steps.len()),
+            1 => outln!(
+                "\x1b[32m{} ✓: {} steps, 1 failure ignored\x1b[0m",
+                name,
+                steps.len()
+            ),
+            _ => outln!(
+                "\x1b[32m{} ✓: {} steps, {} failures ignored\x1b[0m",
+                name,
+                steps.len(),
+                ignored
+            ),
+        }
+        return;
+    }
+
+    match steps
+        .iter()
+        .position(|step| step.status == Status::Failed && !step.continue_on_error)
+    {
+        Some(index) => outln!(
+            "\x1b[31m{} ✗: failed at step {} of {} ({})\x1b[0m",
+            name,
+            index + 1,
+            steps.len(),
+            steps[index].label
+        ),
+        None => {
+            if let Some(result) = outcome.outcome {
+                outln!("\x1b[31m{} ✗: {}\x1b[0m", name, result);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[test]
+    fn test_closure_observer() {
+        let seen = Mutex::new(Vec::new());
+        let observer = |event: &Event<'_>| {
+            if let Event::DirectoryDiscovered { path } = event {
+                seen.lock().unwrap().push(path.to_path_buf());
+            }
+        };
+        observer.on_event(&Event::DirectoryDiscovered {
+            path: Path::new("api"),
+        });
+        observer.on_event(&Event::RunStarted {
+            run_id: "run-1",
+            root: Path::new("."),
+        });
+        assert_eq!(seen.into_inner().unwrap(), vec![Path::new("api")]);
+    }
+}
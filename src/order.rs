@@ -0,0 +1,339 @@
+use crate::report::{RunReport, SkipReason};
+use serde::Serialize;
+use std::cmp::Ordering;
+use std::collections::hash_map::{Entry, RandomState};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::hash::BuildHasher;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use walkdir::WalkDir;
+
+/// The order directories are run in, for `--sort`.
+///
+/// Directories are sorted in ascending order, and `--reverse` reverses it. Ties, and
+/// directories with no value for the key, keep the order they were found in; the
+/// latter come last.
+#[derive(Serialize, Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    /// The order the directories were found in: the immediate children by name, then
+    /// the directories given to `--include`.
+    #[default]
+    #[serde(rename = "none")]
+    #[value(name = "none")]
+    Unsorted,
+    /// By directory name.
+    Name,
+    /// By last modification time, oldest first.
+    Mtime,
+    /// By the total size of the files in the directory, smallest first.
+    Size,
+    /// By how long the command took in the directory the last time loop ran there,
+    /// fastest first.
+    Duration,
+    /// In a random order, different for every run.
+    Random,
+}
+
+/// Removes directories found more than once, such as a child also given to
+/// `--include`, keeping the first.
+///
+/// Paths are compared after resolving symbolic links and `..`; the paths kept are those
+/// found. A directory is run if any of its occurrences is not filtered out.
+pub(crate) fn dedupe(
+    targets: Vec<(PathBuf, Option<SkipReason>)>,
+) -> Vec<(PathBuf, Option<SkipReason>)> {
+    let mut seen: HashMap<PathBuf, usize> = HashMap::new();
+    let mut unique: Vec<(PathBuf, Option<SkipReason>)> = Vec::with_capacity(targets.len());
+    for (dir, reason) in targets {
+        let key = fs::canonicalize(&dir).unwrap_or_else(|_| dir.clone());
+        match seen.entry(key) {
+            Entry::Occupied(entry) => {
+                if reason.is_none() {
+                    unique[*entry.get()].1 = None;
+                }
+            }
+            Entry::Vacant(entry) => {
+                entry.insert(unique.len());
+                unique.push((dir, reason));
+            }
+        }
+    }
+    unique
+}
+
+/// Sorts the directories of a run in place.
+///
+/// Only the directories that run are sorted, among the positions they hold; filtered
+/// directories stay where they are. This keeps `--sort size` from measuring ignored
+/// directories such as `target` or `node_modules`.
+pub(crate) fn sort(targets: &mut [(PathBuf, Option<SkipReason>)], order: SortOrder, reverse: bool) {
+    let slots: Vec<usize> = (0..targets.len())
+        .filter(|&slot| targets[slot].1.is_none())
+        .collect();
+    let mut running: Vec<PathBuf> = slots.iter().map(|&slot| targets[slot].0.clone()).collect();
+    sort_dirs(&mut running, order, reverse);
+    for (slot, dir) in slots.into_iter().zip(running) {
+        targets[slot].0 = dir;
+    }
+}
+
+/// Sorts all the given directories.
+fn sort_dirs(dirs: &mut Vec<PathBuf>, order: SortOrder, reverse: bool) {
+    match order {
+        SortOrder::Unsorted => {
+            if reverse {
+                dirs.reverse();
+            }
+        }
+        SortOrder::Name => sort_by_key(dirs, reverse, |dir| {
+            Some(dir.file_name().unwrap_or_default().to_os_string())
+        }),
+        SortOrder::Mtime => sort_by_key(dirs, reverse, |dir| {
+            fs::metadata(dir).and_then(|meta| meta.modified()).ok()
+        }),
+        SortOrder::Size => sort_by_key(dirs, reverse, |dir| dir.is_dir().then(|| size(dir))),
+        SortOrder::Duration => {
+            let durations = Durations::load();
+            sort_by_key(dirs, reverse, |dir| durations.get(dir))
+        }
+        SortOrder::Random => {
+            let state = RandomState::new();
+            sort_by_key(dirs, reverse, |dir| Some(state.hash_one(dir)))
+        }
+    }
+}
+
+/// Sorts by a key computed once for each directory. Directories without a key come
+/// last, whichever the direction.
+fn sort_by_key<K: Ord>(dirs: &mut Vec<PathBuf>, reverse: bool, key: impl Fn(&Path) -> Option<K>) {
+    let mut keyed: Vec<(Option<K>, PathBuf)> = dirs.drain(..).map(|dir| (key(&dir), dir)).collect();
+    keyed.sort_by(|(a, _), (b, _)| match (a, b) {
+        (Some(a), Some(b)) if reverse => b.cmp(a),
+        (Some(a), Some(b)) => a.cmp(b),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal,
+    });
+    dirs.extend(keyed.into_iter().map(|(_, dir)| dir));
+}
+
+/// The total size of the files below a directory, without following symbolic links.
+fn size(dir: &Path) -> u64 {
+    WalkDir::new(dir)
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_type().is_file())
+        .filter_map(|entry| entry.metadata().ok())
+        .map(|meta| meta.len())
+        .sum()
+}
+
+/// How long commands took in each directory the last time they ran, for
+/// `--sort duration`.
+///
+/// The `loop` command records the durations of runs that use `--sort duration` in
+/// `loop/durations.json` in the user's cache directory: `$XDG_CACHE_HOME`, or
+/// `~/.cache`. Directories are identified by their canonical path.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Durations(BTreeMap<PathBuf, f64>);
+
+impl Durations {
+    /// The file durations are kept in, if a cache directory can be found.
+    pub fn path() -> Option<PathBuf> {
+        std::env::var_os("XDG_CACHE_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))
+            .map(|dir| dir.join("loop").join("durations.json"))
+    }
+
+    /// Reads the recorded durations. A missing or unreadable file gives none.
+    pub fn load() -> Durations {
+        Durations::path()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .and_then(|text| serde_json::from_str(&text).ok())
+            .map(Durations)
+            .unwrap_or_default()
+    }
+
+    /// The last recorded duration of a directory.
+    pub fn get(&self, dir: &Path) -> Option<Duration> {
+        let dir = fs::canonicalize(dir).ok()?;
+        self.0.get(&dir).map(|&secs| Duration::from_secs_f64(secs))
+    }
+
+    /// Updates the durations with those of the directories that ran in a report, and
+    /// forgets directories that no longer exist.
+    pub fn update(&mut self, report: &RunReport) {
+        self.0.retain(|path, _| path.is_dir());
+        for dir in report.ran() {
+            if let Ok(path) = fs::canonicalize(&dir.path) {
+                self.0.insert(path, dir.duration.as_secs_f64());
+            }
+        }
+    }
+
+    /// Adds the durations of a run to those recorded. Failures are ignored, as the
+    /// durations only affect the order of later runs.
+    ///
+    /// The file is replaced in one step, so a concurrent run never reads it half
+    /// written. Of two runs recording at the same time, the last one wins.
+    pub fn record(report: &RunReport) {
+        let Some(path) = Durations::path() else {
+            return;
+        };
+        let mut durations = Durations::load();
+        durations.update(report);
+        if let Some(dir) = path.parent() {
+            let _ = fs::create_dir_all(dir);
+        }
+        let Ok(json) = serde_json::to_string_pretty(&durations.0) else {
+            return;
+        };
+        let temporary = path.with_extension(format!("json.{}", std::process::id()));
+        if fs::write(&temporary, json).is_err() || fs::rename(&temporary, &path).is_err() {
+            let _ = fs::remove_file(&temporary);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ExitPolicy;
+    use crate::executor::Outcome;
+    use crate::report::DirOutcome;
+    use tempfile::tempdir;
+
+    fn names<T>(targets: &[(PathBuf, T)]) -> Vec<String> {
+        targets
+            .iter()
+            .map(|(dir, _)| dir.file_name().unwrap().to_string_lossy().into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn test_dedupe() {
+        let temp_dir = tempdir().unwrap();
+        let root = temp_dir.path();
+        for dir in ["api", "web"] {
+            fs::create_dir(root.join(dir)).unwrap();
+        }
+
+        let targets = vec![
+            (root.join("api"), Some(SkipReason::Excluded)),
+            (root.join("web"), None),
+            (root.join("web/../api"), None),
+            (root.join("./web"), Some(SkipReason::Ignored)),
+        ];
+        let targets = dedupe(targets);
+        assert_eq!(
+            targets,
+            vec![(root.join("api"), None), (root.join("web"), None)]
+        );
+    }
+
+    #[test]
+    fn test_sort() {
+        let temp_dir = tempdir().unwrap();
+        let root = temp_dir.path();
+        for (dir, bytes) in [("b", 30), ("c", 10), ("a", 20)] {
+            fs::create_dir(root.join(dir)).unwrap();
+            fs::write(root.join(dir).join("file"), vec![0; bytes]).unwrap();
+        }
+        let found = vec![
+            (root.join("b"), None),
+            (root.join("missing"), None),
+            (root.join("c"), None),
+            (root.join("a"), None),
+        ];
+
+        let mut targets = found.clone();
+        sort(&mut targets, SortOrder::Unsorted, false);
+        assert_eq!(names(&targets), vec!["b", "missing", "c", "a"]);
+        sort(&mut targets, SortOrder::Unsorted, true);
+        assert_eq!(names(&targets), vec!["a", "c", "missing", "b"]);
+
+        let mut targets = found.clone();
+        sort(&mut targets, SortOrder::Name, false);
+        assert_eq!(names(&targets), vec!["a", "b", "c", "missing"]);
+        sort(&mut targets, SortOrder::Name, true);
+        assert_eq!(names(&targets), vec!["missing", "c", "b", "a"]);
+
+        let mut targets = found.clone();
+        sort(&mut targets, SortOrder::Size, false);
+        assert_eq!(names(&targets), vec!["c", "a", "b", "missing"]);
+        sort(&mut targets, SortOrder::Size, true);
+        assert_eq!(names(&targets), vec!["b", "a", "c", "missing"]);
+
+        let mut targets = found.clone();
+        sort(&mut targets, SortOrder::Random, false);
+        assert_eq!(targets.len(), found.len());
+
+        let mut targets = found.clone();
+        targets[0].1 = Some(SkipReason::Ignored);
+        sort(&mut targets, SortOrder::Name, true);
+        assert_eq!(names(&targets), vec!["b", "missing", "c", "a"]);
+        assert_eq!(targets[0].1, Some(SkipReason::Ignored));
+    }
+
+    #[test]
+    fn test_durations_forget_missing_directories() {
+        let temp_dir = tempdir().unwrap();
+        let kept = fs::canonicalize(temp_dir.path()).unwrap();
+        let mut durations = Durations(BTreeMap::from([
+            (kept.clone(), 1.0),
+            (kept.join("removed"), 2.0),
+        ]));
+        durations.update(&RunReport {
+            run_id: "run-1".to_string(),
+            directories: Vec::new(),
+            interrupted: None,
+            exit_policy: ExitPolicy::First,
+            duration: Duration::ZERO,
+        });
+        assert_eq!(durations, Durations(BTreeMap::from([(kept, 1.0)])));
+    }
+
+    #[test]
+    fn test_durations_update() {
+        let temp_dir = tempdir().unwrap();
+        let root = temp_dir.path();
+        for dir in ["fast", "slow"] {
+            fs::create_dir(root.join(dir)).unwrap();
+        }
+        let report = RunReport {
+            run_id: "run-1".to_string(),
+            directories: vec![
+                DirOutcome::finished(
+                    root.join("slow"),
+                    Ok(Outcome::Exited(0)),
+                    Duration::from_secs(3),
+                ),
+                DirOutcome::finished(
+                    root.join("fast"),
+                    Ok(Outcome::Exited(1)),
+                    Duration::from_secs(1),
+                ),
+                DirOutcome::skipped(root.join("skipped"), SkipReason::Excluded),
+            ],
+            interrupted: None,
+            exit_policy: ExitPolicy::First,
+            duration: Duration::from_secs(4),
+        };
+
+        let mut durations = Durations::default();
+        durations.update(&report);
+        assert_eq!(
+            durations.get(&root.join("slow")),
+            Some(Duration::from_secs(3))
+        );
+        assert_eq!(
+            durations.get(&root.join("./fast")),
+            Some(Duration::from_secs(1))
+        );
+        assert_eq!(durations.get(&root.join("skipped")), None);
+    }
+}
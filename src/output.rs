@@ -0,0 +1,188 @@
+use crate::observer::{Event, Observer, Stream};
+use crate::report::CapturedOutput;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::process::Child;
+use std::sync::mpsc;
+use std::thread;
+
+/// What happens to the output of the commands a loop runs.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutputMode {
+    /// Commands write directly to loop's stdout and stderr. This is the default.
+    #[default]
+    Inherit,
+    /// Output is kept in each directory's `DirOutcome`, up to `limit` bytes per stream,
+    /// and each line is reported to the observer as an `Event::OutputLine` rather than
+    /// written to loop's streams. `ConsoleObserver` prints these lines, so to keep
+    /// output off the terminal, use an observer that does not.
+    Capture { limit: usize },
+    /// Output is printed as with `Inherit` and also kept, up to `limit` bytes per
+    /// stream.
+    Tee { limit: usize },
+    /// Output is thrown away.
+    Discard,
+}
+
+impl OutputMode {
+    /// The number of bytes of each stream kept by [`OutputMode::capture`] and
+    /// [`OutputMode::tee`]: 1 MiB.
+    pub const DEFAULT_LIMIT: usize = 1024 * 1024;
+
+    /// Captures output with the default limit.
+    pub fn capture() -> OutputMode {
+        OutputMode::Capture {
+            limit: OutputMode::DEFAULT_LIMIT,
+        }
+    }
+
+    /// Prints and captures output with the default limit.
+    pub fn tee() -> OutputMode {
+        OutputMode::Tee {
+            limit: OutputMode::DEFAULT_LIMIT,
+        }
+    }
+
+    /// The number of bytes kept of each stream, or `None` if output is not captured.
+    pub fn limit(&self) -> Option<usize> {
+        match *self {
+            OutputMode::Capture { limit } | OutputMode::Tee { limit } => Some(limit),
+            OutputMode::Inherit | OutputMode::Discard => None,
+        }
+    }
+}
+
+/// Reads a command's piped stdout and stderr until both are closed.
+///
+/// The output is kept in `captured` up to the mode's limit. With `Tee` it is also
+/// written to loop's own streams as it arrives; with `Capture` each line is reported to
+/// the observer instead.
+pub(crate) fn collect(
+    child: &mut Child,
+    dir: &Path,
+    mode: OutputMode,
+    observer: &dyn Observer,
+    captured: &mut CapturedOutput,
+) {
+    let limit = mode.limit().unwrap_or(usize::MAX);
+    // Observers need not be thread-safe, so the streams are read on their own threads
+    // and handled here, in the order the chunks arrive.
+    let (sender, receiver) = mpsc::channel();
+    if let Some(stdout) = child.stdout.take() {
+        read(stdout, Stream::Stdout, sender.clone());
+    }
+    if let Some(stderr) = child.stderr.take() {
+        read(stderr, Stream::Stderr, sender.clone());
+    }
+    drop(sender);
+
+    let mut lines = [LineBuffer::default(), LineBuffer::default()];
+    let emit = |stream: Stream, line: &str| {
+        observer.on_event(&Event::OutputLine {
+            path: dir,
+            stream,
+            line,
+        })
+    };
+    for (stream, chunk) in receiver {
+        captured.push(stream, &chunk, limit);
+        match mode {
+            OutputMode::Tee { .. } => write(stream, &chunk),
+            OutputMode::Capture { .. } => {
+                lines[stream as usize].push(&chunk, limit, |line| emit(stream, line))
+            }
+            OutputMode::Inherit | OutputMode::Discard => {}
+        }
+    }
+    for (buffer, stream) in lines.iter_mut().zip([Stream::Stdout, Stream::Stderr]) {
+        buffer.finish(|line| emit(stream, line));
+    }
+}
+
+/// Sends the chunks read from a stream to `sender` from a new thread, until the stream
+/// is closed.
+fn read(
+    mut reader: impl Read + Send + 'static,
+    stream: Stream,
+    sender: mpsc::Sender<(Stream, Vec<u8>)>,
+) {
+    thread::spawn(move || {
+        let mut buffer = [0; 8192];
+        loop {
+            match reader.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(n) => {
+                    if sender.send((stream, buffer[..n].to_vec())).is_err() {
+                        break;
+                    }
+                }
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(_) => break,
+            }
+        }
+    });
+}
+
+fn write(stream: Stream, bytes: &[u8]) {
+    // Output that cannot be printed is still captured, as when a command's own write fails
+    let _ = match stream {
+        Stream::Stdout => io::stdout().write_all(bytes).and(io::stdout().flush()),
+        Stream::Stderr => io::stderr().write_all(bytes),
+    };
+}
+
+/// Splits chunks of output into lines, holding back an incomplete last line.
+#[derive(Default)]
+pub(crate) struct LineBuffer(Vec<u8>);
+
+impl LineBuffer {
+    /// Adds a chunk and calls `emit` with each line it completes. A line longer than
+    /// `limit` is emitted in pieces.
+    pub(crate) fn push(&mut self, chunk: &[u8], limit: usize, mut emit: impl FnMut(&str)) {
+        self.0.extend_from_slice(chunk);
+        let mut start = 0;
+        while let Some(end) = self.0[start..].iter().position(|&byte| byte == b'\n') {
+            emit(&line(&self.0[start..start + end]));
+            start += end + 1;
+        }
+        self.0.drain(..start);
+        if self.0.len() > limit {
+            self.finish(emit);
+        }
+    }
+
+    /// Emits the incomplete last line, if any.
+    pub(crate) fn finish(&mut self, mut emit: impl FnMut(&str)) {
+        if !self.0.is_empty() {
+            emit(&line(&self.0));
+            self.0.clear();
+        }
+    }
+}
+
+/// Converts a line to text, without its line ending. Invalid UTF-8 is replaced.
+pub(crate) fn line(bytes: &[u8]) -> String {
+    let bytes = bytes.strip_suffix(b"\n").unwrap_or(bytes);
+    let bytes = bytes.strip_suffix(b"\r").unwrap_or(bytes);
+    String::from_utf8_lossy(bytes).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_line_buffer() {
+        let mut buffer = LineBuffer::default();
+        let mut lines = Vec::new();
+        buffer.push(b"one\r\ntw", 100, |line| lines.push(line.to_string()));
+        buffer.push(b"o\nthree", 100, |line| lines.push(line.to_string()));
+        assert_eq!(lines, vec!["one", "two"]);
+
+        buffer.finish(|line| lines.push(line.to_string()));
+        assert_eq!(lines, vec!["one", "two", "three"]);
+
+        buffer.push(b"abcdef", 4, |line| lines.push(line.to_string()));
+        assert_eq!(lines.last().unwrap(), "abcdef");
+    }
+}
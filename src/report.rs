@@ -0,0 +1,333 @@
+use crate::config::ExitPolicy;
+use crate::error::LoopError;
+use crate::executor::{self, Outcome};
+use crate::observer::Stream;
+use crate::signals;
+use std::fmt;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Whether a directory or pipeline step ran, and if so whether it succeeded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    /// The command exited with code 0.
+    Succeeded,
+    /// The command failed, was killed by a signal, or could not be started.
+    Failed,
+    /// The command was not run.
+    Skipped,
+}
+
+/// Why a directory was not run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SkipReason {
+    /// `--include-only` does not list the directory.
+    NotIncluded,
+    /// `--exclude`, `--exclude-only` or `--exclude-pattern` matches the directory.
+    Excluded,
+    /// The directory is not a member of any group given to `--group`.
+    NotInGroup,
+    /// The directory does not match `--include-pattern`.
+    NotMatched,
+    /// The directory is listed in the `ignore` setting.
+    Ignored,
+    /// A path given to `--include` is not a directory.
+    NotADirectory,
+    /// The run was interrupted by a signal before the directory was reached.
+    Interrupted,
+    /// The run was cancelled before the directory was reached.
+    Cancelled,
+}
+
+impl fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let reason = match self {
+            SkipReason::NotIncluded => "not included",
+            SkipReason::Excluded => "excluded",
+            SkipReason::NotInGroup => "not in group",
+            SkipReason::NotMatched => "does not match pattern",
+            SkipReason::Ignored => "ignored",
+            SkipReason::NotADirectory => "not a directory",
+            SkipReason::Interrupted => "interrupted",
+            SkipReason::Cancelled => "cancelled",
+        };
+        f.write_str(reason)
+    }
+}
+
+/// The output a command printed, when it was captured.
+///
+/// Each stream keeps at most the limit set by the output mode; the rest is dropped and
+/// `truncated` is set. The output of a pipeline's steps is kept together.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CapturedOutput {
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+    /// Whether output was dropped because a stream reached the limit.
+    pub truncated: bool,
+}
+
+impl CapturedOutput {
+    /// Appends bytes written to a stream, keeping at most `limit` bytes of it.
+    pub(crate) fn push(&mut self, stream: Stream, bytes: &[u8], limit: usize) {
+        let buffer = match stream {
+            Stream::Stdout => &mut self.stdout,
+            Stream::Stderr => &mut self.stderr,
+        };
+        let room = limit.saturating_sub(buffer.len());
+        if bytes.len() > room {
+            self.truncated = true;
+        }
+        buffer.extend_from_slice(&bytes[..bytes.len().min(room)]);
+    }
+
+    /// Appends the output of another command, such as the next step of a pipeline.
+    pub(crate) fn append(&mut self, other: &CapturedOutput, limit: usize) {
+        self.push(Stream::Stdout, &other.stdout, limit);
+        self.push(Stream::Stderr, &other.stderr, limit);
+        self.truncated |= other.truncated;
+    }
+}
+
+/// The result of one step of a pipeline.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StepOutcome {
+    /// The step's name, or its command if it has none.
+    pub label: String,
+    pub status: Status,
+    /// How the step's command ended, if it ran.
+    pub outcome: Option<Outcome>,
+    pub duration: Duration,
+    /// Whether a failure of the step lets the pipeline continue.
+    pub continue_on_error: bool,
+}
+
+/// The result of a run in one directory.
+#[derive(Debug)]
+pub struct DirOutcome {
+    pub path: PathBuf,
+    pub status: Status,
+    /// How the command ended. For a pipeline, this is the step that stopped it, or
+    /// success. `None` if the command was skipped or could not be started.
+    pub outcome: Option<Outcome>,
+    /// The error that prevented the command from running, such as a failure to start it.
+    pub error: Option<LoopError>,
+    /// How long the command ran.
+    pub duration: Duration,
+    /// What the command printed, if output was captured.
+    pub output: Option<CapturedOutput>,
+    /// Why the directory was not run, if it was skipped.
+    pub skip_reason: Option<SkipReason>,
+    /// The results of each step, if a pipeline was run.
+    pub steps: Vec<StepOutcome>,
+}
+
+impl DirOutcome {
+    /// The result of a command that was run, or could not be run.
+    pub fn finished(
+        path: PathBuf,
+        result: Result<Outcome, LoopError>,
+        duration: Duration,
+    ) -> DirOutcome {
+        let (status, outcome, error) = match result {
+            Ok(outcome) if outcome.success() => (Status::Succeeded, Some(outcome), None),
+            Ok(outcome) => (Status::Failed, Some(outcome), None),
+            Err(err) => (Status::Failed, None, Some(err)),
+        };
+        DirOutcome {
+            path,
+            status,
+            outcome,
+            error,
+            duration,
+            output: None,
+            skip_reason: None,
+            steps: Vec::new(),
+        }
+    }
+
+    /// A directory that was not run.
+    pub fn skipped(path: PathBuf, reason: SkipReason) -> DirOutcome {
+        DirOutcome {
+            path,
+            status: Status::Skipped,
+            outcome: None,
+            error: None,
+            duration: Duration::ZERO,
+            output: None,
+            skip_reason: Some(reason),
+            steps: Vec::new(),
+        }
+    }
+
+    /// Converts the result of a directory that ran back into the command's outcome, or
+    /// the error that prevented it from running. A skipped directory gives success.
+    pub fn into_result(self) -> Result<Outcome, LoopError> {
+        match self.error {
+            Some(err) => Err(err),
+            None => Ok(self.outcome.unwrap_or(Outcome::Exited(0))),
+        }
+    }
+
+    /// Whether the command was run, successfully or not.
+    pub fn ran(&self) -> bool {
+        self.status != Status::Skipped
+    }
+
+    /// The exit code of the directory: the command's exit code, 128 plus the signal that
+    /// killed it, or the exit code of the error that prevented it from running. `None`
+    /// if the directory was skipped.
+    pub fn exit_code(&self) -> Option<i32> {
+        self.outcome
+            .map(Outcome::exit_code)
+            .or_else(|| self.error.as_ref().map(LoopError::exit_code))
+    }
+
+    /// The signal that killed the command, if any.
+    pub fn signal(&self) -> Option<i32> {
+        match self.outcome {
+            Some(Outcome::Signaled(signal)) => Some(signal),
+            _ => None,
+        }
+    }
+}
+
+/// The result of a whole run, with one entry per directory considered.
+///
+/// Directories appear in the order they were considered, including those filtered out
+/// or not reached because of a signal.
+#[derive(Debug)]
+pub struct RunReport {
+    /// The identifier exported to commands as `LOOP_RUN_ID`.
+    pub run_id: String,
+    pub directories: Vec<DirOutcome>,
+    /// The signal that interrupted the run, if any.
+    pub interrupted: Option<i32>,
+    /// How the exit code is derived from the directories' exit codes.
+    pub exit_policy: ExitPolicy,
+    pub duration: Duration,
+}
+
+impl RunReport {
+    /// The directories in which a command was run.
+    pub fn ran(&self) -> impl Iterator<Item = &DirOutcome> {
+        self.directories.iter().filter(|dir| dir.ran())
+    }
+
+    /// The directories in which the command failed or could not be run.
+    pub fn failed(&self) -> impl Iterator<Item = &DirOutcome> {
+        self.directories
+            .iter()
+            .filter(|dir| dir.status == Status::Failed)
+    }
+
+    /// The directories that were not run.
+    pub fn skipped(&self) -> impl Iterator<Item = &DirOutcome> {
+        self.directories.iter().filter(|dir| !dir.ran())
+    }
+
+    /// Whether every directory that ran succeeded and the run was not interrupted.
+    pub fn success(&self) -> bool {
+        self.exit_code() == exitcode::OK
+    }
+
+    /// The exit code of the run.
+    ///
+    /// An interrupted run exits with 128 plus the signal number. Otherwise the exit
+    /// codes of the directories that ran are combined according to the exit policy.
+    pub fn exit_code(&self) -> i32 {
+        if let Some(signal) = self.interrupted {
+            return signals::exit_code(signal);
+        }
+        let outcomes: Vec<Outcome> = self
+            .ran()
+            .filter_map(DirOutcome::exit_code)
+            .map(Outcome::Exited)
+            .collect();
+        executor::exit_status(&outcomes, self.exit_policy)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+
+    fn report(directories: Vec<DirOutcome>) -> RunReport {
+        RunReport {
+            run_id: "run-1".to_string(),
+            directories,
+            interrupted: None,
+            exit_policy: ExitPolicy::First,
+            duration: Duration::ZERO,
+        }
+    }
+
+    #[test]
+    fn test_dir_outcome_statuses() {
+        let ok = DirOutcome::finished("a".into(), Ok(Outcome::Exited(0)), Duration::ZERO);
+        assert_eq!(ok.status, Status::Succeeded);
+        assert_eq!(ok.exit_code(), Some(0));
+
+        let killed = DirOutcome::finished("b".into(), Ok(Outcome::Signaled(9)), Duration::ZERO);
+        assert_eq!(killed.status, Status::Failed);
+        assert_eq!(killed.signal(), Some(9));
+        assert_eq!(killed.exit_code(), Some(137));
+
+        let spawn = LoopError::Spawn {
+            program: "missing".to_string(),
+            source: io::Error::from(io::ErrorKind::NotFound),
+        };
+        let missing = DirOutcome::finished("c".into(), Err(spawn), Duration::ZERO);
+        assert_eq!(missing.status, Status::Failed);
+        assert_eq!(missing.exit_code(), Some(127));
+
+        let skipped = DirOutcome::skipped("d".into(), SkipReason::Ignored);
+        assert!(!skipped.ran());
+        assert_eq!(skipped.exit_code(), None);
+        assert_eq!(skipped.skip_reason.unwrap().to_string(), "ignored");
+    }
+
+    #[test]
+    fn test_run_report_exit_code() {
+        let mut run = report(vec![
+            DirOutcome::skipped("a".into(), SkipReason::Excluded),
+            DirOutcome::finished("b".into(), Ok(Outcome::Exited(0)), Duration::ZERO),
+            DirOutcome::finished("c".into(), Ok(Outcome::Exited(3)), Duration::ZERO),
+            DirOutcome::finished("d".into(), Ok(Outcome::Exited(1)), Duration::ZERO),
+        ]);
+        assert_eq!(run.ran().count(), 3);
+        assert_eq!(run.failed().count(), 2);
+        assert_eq!(run.skipped().count(), 1);
+        assert_eq!(run.exit_code(), 3);
+
+        run.exit_policy = ExitPolicy::Count;
+        assert_eq!(run.exit_code(), 2);
+
+        run.interrupted = Some(signals::SIGTERM);
+        assert_eq!(run.exit_code(), 143);
+        assert!(!run.success());
+
+        assert!(report(vec![]).success());
+    }
+
+    #[test]
+    fn test_captured_output_limit() {
+        let mut output = CapturedOutput::default();
+        output.push(Stream::Stdout, b"abc", 4);
+        output.push(Stream::Stderr, b"oops", 4);
+        assert!(!output.truncated);
+
+        output.push(Stream::Stdout, b"def", 4);
+        assert_eq!(output.stdout, b"abcd");
+        assert!(output.truncated);
+
+        let mut pipeline = CapturedOutput::default();
+        pipeline.append(&output, 2);
+        assert_eq!(
+            (pipeline.stdout.as_slice(), pipeline.stderr.as_slice()),
+            (&b"ab"[..], &b"oo"[..])
+        );
+        assert!(pipeline.truncated);
+    }
+}
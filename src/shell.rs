@@ -0,0 +1,356 @@
+use std::path::Path;
+
+/// The families of shells loop knows how to quote for.
+///
+/// Bash and zsh share POSIX quoting rules but source different rc files; fish has
+/// its own quoting rules and rc file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShellKind {
+    Posix,
+    Bash,
+    Zsh,
+    Fish,
+}
+
+impl ShellKind {
+    /// Determines the kind of shell from its path or name, such as `/bin/zsh`.
+    ///
+    /// Unknown shells are assumed to be POSIX compatible.
+    pub fn from_shell(shell: &str) -> ShellKind {
+        let name = Path::new(shell)
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or(shell);
+        match name {
+            "bash" => ShellKind::Bash,
+            "zsh" => ShellKind::Zsh,
+            "fish" => ShellKind::Fish,
+            _ => ShellKind::Posix,
+        }
+    }
+
+    /// Quotes a single argument so the shell passes it on unchanged.
+    ///
+    /// Arguments made only of characters the shell never interprets are returned as
+    /// they are, so that a bare command name can still resolve to an alias. A leading
+    /// `=` is always quoted, since zsh expands `=cmd` to the path of `cmd`.
+    pub fn quote(self, arg: &str) -> String {
+        let is_safe = |c: char| {
+            c.is_ascii_alphanumeric()
+                || matches!(c, '_' | '-' | '.' | '/' | ',' | ':' | '=' | '+' | '@')
+                || (c == '%' && self != ShellKind::Fish)
+        };
+        if !arg.is_empty() && !arg.starts_with('=') && arg.chars().all(is_safe) {
+            return arg.to_string();
+        }
+
+        match self {
+            ShellKind::Fish => format!("'{}'", arg.replace('\\', "\\\\").replace('\'', "\\'")),
+            _ => format!("'{}'", arg.replace('\'', r#"'\''"#)),
+        }
+    }
+
+    /// Turns a command into shell code.
+    ///
+    /// A single element is taken to be shell code already, as in `loop "git status"`,
+    /// and is used as is. Several elements are treated as separate arguments and each
+    /// is quoted, so `loop grep "two words" file` behaves as if typed in the shell.
+    pub fn command_line(self, command: &[String]) -> String {
+        match command {
+            [script] => script.clone(),
+            args => self.quote_args(args),
+        }
+    }
+
+    /// Quotes each argument and joins them with spaces.
+    pub fn quote_args(self, args: &[String]) -> String {
+        args.iter()
+            .map(|arg| self.quote(arg))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// Builds the script passed to `shell -c` for the given shell code.
+    ///
+    /// When `source_rc` is set, the user's rc file is sourced first so that aliases and
+    /// functions are available. Zsh runs the code through `eval` so that aliases
+    /// defined by the rc file are expanded.
+    pub fn script(self, code: &str, source_rc: bool) -> String {
+        if !source_rc {
+            return code.to_string();
+        }
+
+        match self {
+            ShellKind::Zsh => format!(
+                "source ~/.zshrc 2>/dev/null\neval {}\n",
+                ShellKind::Posix.quote(code)
+            ),
+            ShellKind::Fish => format!("source ~/.config/fish/config.fish 2>/dev/null\n{}\n", code),
+            ShellKind::Posix | ShellKind::Bash => {
+                format!("if [ -f ~/.bashrc ]; then . ~/.bashrc; fi\n{}\n", code)
+            }
+        }
+    }
+}
+
+/// Splits a command line into words by POSIX shell quoting rules, without expanding
+/// anything.
+///
+/// Words are separated by unquoted whitespace. Single quotes keep everything up to the
+/// next single quote, double quotes keep everything but `\"`, `\\`, `\$` and `` \` ``
+/// escapes, and a backslash outside quotes keeps the next character. An unterminated
+/// quote runs to the end of the line.
+pub fn split_words(line: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => words.extend(word.take()),
+            '\'' => {
+                let word = word.get_or_insert_with(String::new);
+                word.extend(chars.by_ref().take_while(|&c| c != '\''));
+            }
+            '"' => {
+                let word = word.get_or_insert_with(String::new);
+                while let Some(c) = chars.next() {
+                    match c {
+                        '"' => break,
+                        '\\' => match chars.next() {
+                            Some(next @ ('"' | '\\' | '$' | '`')) => word.push(next),
+                            Some(next) => {
+                                word.push('\\');
+                                word.push(next);
+                            }
+                            None => word.push('\\'),
+                        },
+                        c => word.push(c),
+                    }
+                }
+            }
+            '\\' => {
+                let word = word.get_or_insert_with(String::new);
+                match chars.next() {
+                    // A backslash before a newline continues the line
+                    Some('\n') => {}
+                    Some(next) => word.push(next),
+                    None => word.push('\\'),
+                }
+            }
+            c => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    words.extend(word);
+    words
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::Command;
+
+    /// Arguments that are easy to mangle when building shell code.
+    const TRICKY_ARGS: &[&str] = &[
+        "plain",
+        "two words",
+        "",
+        "$HOME",
+        "${HOME}",
+        "`echo injected`",
+        "$(echo injected)",
+        "it's",
+        "\"double\"",
+        "back\\slash",
+        "trailing\\",
+        "*.rs",
+        "~",
+        "a;b&&c|d",
+        "tab\there",
+        "new\nline",
+        "{a,b}",
+        "%self",
+        "!bang",
+        "#hash",
+        "'",
+        "=x",
+        "a=b",
+    ];
+
+    fn args(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    #[test]
+    fn test_from_shell() {
+        assert_eq!(ShellKind::from_shell("/bin/zsh"), ShellKind::Zsh);
+        assert_eq!(
+            ShellKind::from_shell("/usr/local/bin/fish"),
+            ShellKind::Fish
+        );
+        assert_eq!(ShellKind::from_shell("bash"), ShellKind::Bash);
+        assert_eq!(ShellKind::from_shell("/bin/dash"), ShellKind::Posix);
+    }
+
+    #[test]
+    fn test_quote_posix() {
+        let shell = ShellKind::Posix;
+        assert_eq!(shell.quote("gst"), "gst");
+        assert_eq!(shell.quote("--flag=a/b.c"), "--flag=a/b.c");
+        assert_eq!(shell.quote("=x"), "'=x'");
+        assert_eq!(shell.quote(""), "''");
+        assert_eq!(shell.quote("two words"), "'two words'");
+        assert_eq!(shell.quote("$HOME"), "'$HOME'");
+        assert_eq!(shell.quote("it's"), r#"'it'\''s'"#);
+        assert_eq!(ShellKind::Zsh.quote("it's"), r#"'it'\''s'"#);
+    }
+
+    #[test]
+    fn test_quote_fish() {
+        let shell = ShellKind::Fish;
+        assert_eq!(shell.quote("gst"), "gst");
+        assert_eq!(shell.quote("two words"), "'two words'");
+        assert_eq!(shell.quote("it's"), r#"'it\'s'"#);
+        assert_eq!(shell.quote(r"back\slash"), r"'back\\slash'");
+        assert_eq!(shell.quote("%self"), "'%self'");
+        assert_eq!(shell.quote("$HOME"), "'$HOME'");
+    }
+
+    /// Checks the exact quoting of the tricky arguments, so that the zsh and fish rules
+    /// are covered even where those shells are not installed.
+    #[test]
+    fn test_quote_tricky_args_for_zsh_and_fish() {
+        let quote_all = |kind: ShellKind| -> Vec<String> {
+            TRICKY_ARGS.iter().map(|arg| kind.quote(arg)).collect()
+        };
+
+        assert_eq!(
+            quote_all(ShellKind::Zsh),
+            args(&[
+                "plain",
+                "'two words'",
+                "''",
+                "'$HOME'",
+                "'${HOME}'",
+                "'`echo injected`'",
+                "'$(echo injected)'",
+                r"'it'\''s'",
+                r#"'"double"'"#,
+                r"'back\slash'",
+                r"'trailing\'",
+                "'*.rs'",
+                "'~'",
+                "'a;b&&c|d'",
+                "'tab\there'",
+                "'new\nline'",
+                "'{a,b}'",
+                "%self",
+                "'!bang'",
+                "'#hash'",
+                r"''\'''",
+                "'=x'",
+                "a=b",
+            ])
+        );
+
+        assert_eq!(
+            quote_all(ShellKind::Fish),
+            args(&[
+                "plain",
+                "'two words'",
+                "''",
+                "'$HOME'",
+                "'${HOME}'",
+                "'`echo injected`'",
+                "'$(echo injected)'",
+                r"'it\'s'",
+                r#"'"double"'"#,
+                r"'back\\slash'",
+                r"'trailing\\'",
+                "'*.rs'",
+                "'~'",
+                "'a;b&&c|d'",
+                "'tab\there'",
+                "'new\nline'",
+                "'{a,b}'",
+                "'%self'",
+                "'!bang'",
+                "'#hash'",
+                r"'\''",
+                "'=x'",
+                "a=b",
+            ])
+        );
+    }
+
+    #[test]
+    fn test_command_line() {
+        let shell = ShellKind::Posix;
+        assert_eq!(
+            shell.command_line(&args(&["git status | head"])),
+            "git status | head"
+        );
+        assert_eq!(
+            shell.command_line(&args(&["grep", "two words", "file"])),
+            "grep 'two words' file"
+        );
+    }
+
+    #[test]
+    fn test_split_words() {
+        assert_eq!(
+            split_words(r#"git commit -m 'wip  thing' "a \"b\"" c\ d"#),
+            args(&["git", "commit", "-m", "wip  thing", "a \"b\"", "c d"])
+        );
+        assert_eq!(split_words("  ''  "), args(&[""]));
+        assert_eq!(
+            split_words(&ShellKind::Posix.quote_args(&args(TRICKY_ARGS))),
+            args(TRICKY_ARGS)
+        );
+    }
+
+    #[test]
+    fn test_zsh_script_evals_quoted_code() {
+        let script = ShellKind::Zsh.script(r#"echo "$HOME" 'x'"#, true);
+        assert_eq!(
+            script,
+            "source ~/.zshrc 2>/dev/null\neval 'echo \"$HOME\" '\\''x'\\'''\n"
+        );
+    }
+
+    /// Runs `printf` with the tricky arguments through every installed shell and checks
+    /// that each argument arrives unchanged.
+    #[test]
+    fn test_quoting_matrix_in_installed_shells() {
+        let mut command = args(&["printf", "[%s]\\n"]);
+        command.extend(args(TRICKY_ARGS));
+        let expected: String = TRICKY_ARGS
+            .iter()
+            .map(|arg| format!("[{}]\n", arg))
+            .collect();
+
+        for shell in ["sh", "dash", "bash", "zsh", "fish"] {
+            let kind = ShellKind::from_shell(shell);
+            for source_rc in [false, true] {
+                let script = kind.script(&kind.command_line(&command), source_rc);
+                let output = match Command::new(shell)
+                    .arg("-c")
+                    .arg(&script)
+                    .env("HOME", "/nonexistent")
+                    .output()
+                {
+                    Ok(output) => output,
+                    // The shell is not installed.
+                    Err(_) => continue,
+                };
+
+                assert_eq!(
+                    String::from_utf8_lossy(&output.stdout),
+                    expected,
+                    "{} mangled arguments in script:\n{}",
+                    shell,
+                    script
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,208 @@
+use std::process::Command;
+use std::sync::{Mutex, Once};
+
+#[cfg(unix)]
+pub use libc::{SIGINT, SIGTERM};
+#[cfg(not(unix))]
+pub const SIGINT: i32 = 2;
+#[cfg(not(unix))]
+pub const SIGTERM: i32 = 15;
+
+/// Every signal received since the handlers were installed, in order.
+static RECEIVED: Mutex<Vec<i32>> = Mutex::new(Vec::new());
+
+/// The commands currently running, so that signals can be forwarded to them.
+static RUNNING: Mutex<Vec<RunningChild>> = Mutex::new(Vec::new());
+
+static INSTALL: Once = Once::new();
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct RunningChild {
+    pid: u32,
+    own_group: bool,
+}
+
+/// Installs handlers for SIGINT and SIGTERM for the rest of the process.
+///
+/// Once installed, the signals no longer terminate the process. Instead, each signal is
+/// recorded, so that runs in progress start no further directories, and every signal is
+/// forwarded to the running commands. The `loop` binary installs the handlers at
+/// startup; library callers that want the same behaviour call this themselves, as it
+/// takes over the signals from the host. Installing more than once has no effect.
+pub fn install() {
+    INSTALL.call_once(|| {
+        #[cfg(unix)]
+        match signal_hook::iterator::Signals::new([SIGINT, SIGTERM]) {
+            Ok(mut signals) => {
+                std::thread::spawn(move || {
+                    for signal in signals.forever() {
+                        RECEIVED.lock().unwrap().push(signal);
+                        forward(signal);
+                    }
+                });
+            }
+            Err(err) => errln!("Warning: failed to install signal handlers: {}", err),
+        }
+    });
+}
+
+/// The signals that interrupt one run.
+///
+/// Taken when the run starts, it sees only the signals received after that. A signal
+/// that stopped an earlier run is not seen, and starting a run does not hide a signal
+/// from other runs in the same process.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Interrupts {
+    since: usize,
+}
+
+impl Interrupts {
+    /// Starts watching for signals from now on.
+    pub fn start() -> Interrupts {
+        Interrupts {
+            since: RECEIVED.lock().unwrap().len(),
+        }
+    }
+
+    /// Returns the first signal received since the run started, if any.
+    pub fn received(&self) -> Option<i32> {
+        RECEIVED.lock().unwrap().get(self.since).copied()
+    }
+}
+
+impl Default for Interrupts {
+    fn default() -> Interrupts {
+        Interrupts::start()
+    }
+}
+
+/// The conventional exit code of a process terminated by a signal, such as 130 for
+/// SIGINT and 143 for SIGTERM.
+pub fn exit_code(signal: i32) -> i32 {
+    128 + signal
+}
+
+/// The name of a signal, such as `SIGINT`.
+pub fn name(signal: i32) -> String {
+    #[cfg(unix)]
+    let names = [
+        (libc::SIGHUP, "SIGHUP"),
+        (libc::SIGINT, "SIGINT"),
+        (libc::SIGQUIT, "SIGQUIT"),
+        (libc::SIGABRT, "SIGABRT"),
+        (libc::SIGKILL, "SIGKILL"),
+        (libc::SIGSEGV, "SIGSEGV"),
+        (libc::SIGPIPE, "SIGPIPE"),
+        (libc::SIGTERM, "SIGTERM"),
+    ];
+    #[cfg(not(unix))]
+    let names = [(SIGINT, "SIGINT"), (SIGTERM, "SIGTERM")];
+
+    names
+        .iter()
+        .find(|(number, _)| *number == signal)
+        .map(|(_, name)| name.to_string())
+        .unwrap_or_else(|| format!("signal {}", signal))
+}
+
+/// Prepares a command so that signals can be forwarded to everything it starts.
+///
+/// When loop's standard input is not a terminal, the command is placed in a process
+/// group of its own, so that a forwarded signal reaches every process the shell starts.
+/// When it is a terminal, the command stays in the terminal's foreground process group,
+/// so that it can still read from the terminal and receives Ctrl-C directly. Returns
+/// whether the command has its own process group.
+pub fn prepare(command: &mut Command) -> bool {
+    #[cfg(unix)]
+    {
+        use std::io::IsTerminal;
+        use std::os::unix::process::CommandExt;
+
+        if !std::io::stdin().is_terminal() {
+            command.process_group(0);
+            return true;
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = command;
+    false
+}
+
+/// Keeps a running command registered for signal forwarding until it is dropped.
+#[derive(Debug)]
+pub struct ChildGuard {
+    child: RunningChild,
+}
+
+/// Registers a running command so that signals are forwarded to it.
+///
+/// If the command's run was interrupted before the command was registered, the signal is
+/// forwarded to the command right away.
+pub fn track(pid: u32, own_group: bool, interrupts: Interrupts) -> ChildGuard {
+    let child = RunningChild { pid, own_group };
+    RUNNING.lock().unwrap().push(child);
+    if let Some(signal) = interrupts.received() {
+        forward_to(child, signal);
+    }
+    ChildGuard { child }
+}
+
+impl Drop for ChildGuard {
+    fn drop(&mut self) {
+        let mut running = RUNNING.lock().unwrap();
+        if let Some(index) = running.iter().position(|child| *child == self.child) {
+            running.remove(index);
+        }
+    }
+}
+
+/// Forwards a signal to every running command.
+fn forward(signal: i32) {
+    for child in RUNNING.lock().unwrap().iter() {
+        forward_to(*child, signal);
+    }
+}
+
+/// Sends a signal to a command's process group, or to the command alone when it shares
+/// loop's process group. SIGINT is not sent in that case, as the terminal has already
+/// delivered it to the whole foreground process group.
+fn forward_to(child: RunningChild, signal: i32) {
+    #[cfg(unix)]
+    {
+        let pid = child.pid as libc::pid_t;
+        if child.own_group {
+            // SAFETY: kill has no memory safety requirements.
+            unsafe { libc::kill(-pid, signal) };
+        } else if signal != SIGINT {
+            // SAFETY: kill has no memory safety requirements.
+            unsafe { libc::kill(pid, signal) };
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = (child, signal);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exit_code_and_name() {
+        assert_eq!(exit_code(SIGINT), 130);
+        assert_eq!(exit_code(SIGTERM), 143);
+        assert_eq!(name(SIGINT), "SIGINT");
+        assert_eq!(name(SIGTERM), "SIGTERM");
+    }
+
+    #[test]
+    fn test_track_registers_until_dropped() {
+        let guard = track(i32::MAX as u32, true, Interrupts::start());
+        assert!(RUNNING.lock().unwrap().contains(&guard.child));
+        drop(guard);
+        assert!(!RUNNING
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|child| child.pid == i32::MAX as u32));
+    }
+}
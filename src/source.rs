@@ -0,0 +1,469 @@
+use crate::error::LoopError;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+/// Finds the directories a loop considers running in.
+///
+/// The directories returned are then filtered by `--include-only`, `--exclude`, groups,
+/// patterns and the `ignore` setting, as the immediate children of the root are by
+/// default. A path that is not a directory is reported as skipped. Sources can be
+/// combined by collecting them in a `Vec<Box<dyn DirectorySource>>`.
+pub trait DirectorySource: fmt::Debug + Send + Sync {
+    /// Returns the directories under `root` to consider, in the order they should run.
+    fn directories(&self, root: &Path) -> Result<Vec<PathBuf>, LoopError>;
+
+    /// Returns the directories like `directories`, without looking inside those for
+    /// which `prune` returns true. The pruned directories themselves are still returned.
+    ///
+    /// The loop passes the directories that are excluded or ignored, so that searching
+    /// the tree does not descend into `target` or `node_modules`. Sources that do not
+    /// search the tree need not implement this.
+    fn pruned_directories(
+        &self,
+        root: &Path,
+        prune: &dyn Fn(&Path) -> bool,
+    ) -> Result<Vec<PathBuf>, LoopError> {
+        let _ = prune;
+        self.directories(root)
+    }
+}
+
+impl<S: DirectorySource + ?Sized> DirectorySource for Box<S> {
+    fn directories(&self, root: &Path) -> Result<Vec<PathBuf>, LoopError> {
+        (**self).directories(root)
+    }
+
+    fn pruned_directories(
+        &self,
+        root: &Path,
+        prune: &dyn Fn(&Path) -> bool,
+    ) -> Result<Vec<PathBuf>, LoopError> {
+        (**self).pruned_directories(root, prune)
+    }
+}
+
+impl<S: DirectorySource> DirectorySource for Vec<S> {
+    fn directories(&self, root: &Path) -> Result<Vec<PathBuf>, LoopError> {
+        self.pruned_directories(root, &|_| false)
+    }
+
+    fn pruned_directories(
+        &self,
+        root: &Path,
+        prune: &dyn Fn(&Path) -> bool,
+    ) -> Result<Vec<PathBuf>, LoopError> {
+        let mut directories = Vec::new();
+        for source in self {
+            directories.extend(source.pruned_directories(root, prune)?);
+        }
+        Ok(directories)
+    }
+}
+
+/// The immediate child directories of the root, in name order. This is the default.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Children;
+
+impl DirectorySource for Children {
+    fn directories(&self, root: &Path) -> Result<Vec<PathBuf>, LoopError> {
+        walk(root, Some(1), |_| false)
+    }
+}
+
+/// Every directory below the root, down to `max_depth` levels if set, with each
+/// directory followed by its children. Hidden directories, such as `.git`, are not
+/// entered.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Recursive {
+    pub max_depth: Option<usize>,
+}
+
+impl DirectorySource for Recursive {
+    fn directories(&self, root: &Path) -> Result<Vec<PathBuf>, LoopError> {
+        self.pruned_directories(root, &|_| false)
+    }
+
+    fn pruned_directories(
+        &self,
+        root: &Path,
+        prune: &dyn Fn(&Path) -> bool,
+    ) -> Result<Vec<PathBuf>, LoopError> {
+        walk(root, self.max_depth, prune)
+    }
+}
+
+/// The Git repositories below the root: directories containing a `.git` directory or
+/// file. Repositories are not searched for nested repositories.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct GitRepositories {
+    pub max_depth: Option<usize>,
+}
+
+impl DirectorySource for GitRepositories {
+    fn directories(&self, root: &Path) -> Result<Vec<PathBuf>, LoopError> {
+        self.pruned_directories(root, &|_| false)
+    }
+
+    fn pruned_directories(
+        &self,
+        root: &Path,
+        prune: &dyn Fn(&Path) -> bool,
+    ) -> Result<Vec<PathBuf>, LoopError> {
+        let repositories = walk(root, self.max_depth, |dir| {
+            dir.join(".git").exists() || prune(dir)
+        })?;
+        Ok(repositories
+            .into_iter()
+            .filter(|dir| dir.join(".git").exists())
+            .collect())
+    }
+}
+
+/// A fixed list of directories, relative to the root unless absolute.
+#[derive(Debug, Clone, Default)]
+pub struct DirectoryList(pub Vec<PathBuf>);
+
+impl DirectorySource for DirectoryList {
+    fn directories(&self, root: &Path) -> Result<Vec<PathBuf>, LoopError> {
+        Ok(self.0.iter().map(|dir| root.join(dir)).collect())
+    }
+}
+
+/// The members of the workspace defined at the root.
+///
+/// Members are read from the `[workspace]` section of `Cargo.toml`, the `workspaces` of
+/// `package.json` and the `packages` of `pnpm-workspace.yaml`, in that order. Glob
+/// patterns are expanded to the matching directories, and members excluded by the
+/// manifest are left out.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct WorkspaceMembers;
+
+impl DirectorySource for WorkspaceMembers {
+    fn directories(&self, root: &Path) -> Result<Vec<PathBuf>, LoopError> {
+        let mut directories = Vec::new();
+
+        if let Some(manifest) = read_manifest::<toml::Table>(root, "Cargo.toml", |text| {
+            toml::from_str(text).map_err(|err| err.to_string())
+        })? {
+            let workspace = manifest.get("workspace");
+            let members = string_list(workspace.and_then(|ws| ws.get("members")));
+            let exclude = string_list(workspace.and_then(|ws| ws.get("exclude")));
+            directories.extend(expand_members(root, &members, &exclude)?);
+        }
+
+        if let Some(manifest) = read_manifest::<serde_json::Value>(root, "package.json", |text| {
+            serde_json::from_str(text).map_err(|err| err.to_string())
+        })? {
+            let workspaces = manifest.get("workspaces").map(|workspaces| {
+                workspaces
+                    .get("packages")
+                    .cloned()
+                    .unwrap_or_else(|| workspaces.clone())
+            });
+            let patterns: Vec<String> = workspaces
+                .and_then(|workspaces| serde_json::from_value(workspaces).ok())
+                .unwrap_or_default();
+            directories.extend(expand_negated(root, &patterns)?);
+        }
+
+        if let Some(manifest) =
+            read_manifest::<serde_yaml_ng::Value>(root, "pnpm-workspace.yaml", |text| {
+                serde_yaml_ng::from_str(text).map_err(|err| err.to_string())
+            })?
+        {
+            let patterns: Vec<String> = manifest
+                .get("packages")
+                .and_then(|packages| serde_yaml_ng::from_value(packages.clone()).ok())
+                .unwrap_or_default();
+            directories.extend(expand_negated(root, &patterns)?);
+        }
+
+        Ok(directories)
+    }
+}
+
+/// Walks the directories below `root` in name order, down to `max_depth` levels,
+/// without entering hidden directories or those for which `stop` returns true.
+///
+/// Only an unreadable root is an error. Directories below it that cannot be read are
+/// skipped, so that one of them does not stop the whole search.
+fn walk(
+    root: &Path,
+    max_depth: Option<usize>,
+    stop: impl Fn(&Path) -> bool,
+) -> Result<Vec<PathBuf>, LoopError> {
+    let mut walker = WalkDir::new(root).min_depth(1).sort_by_file_name();
+    if let Some(max_depth) = max_depth {
+        walker = walker.max_depth(max_depth);
+    }
+
+    let mut directories = Vec::new();
+    let mut entries = walker.into_iter();
+    while let Some(entry) = entries.next() {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(err) if err.depth() > 0 => continue,
+            Err(err) => {
+                return Err(LoopError::io(
+                    format!("failed to read {}", root.display()),
+                    err.into(),
+                ))
+            }
+        };
+        if !entry.file_type().is_dir() {
+            continue;
+        }
+        // Immediate children are listed even when hidden, so that filters such as the
+        // `ignore` setting decide on them, as they always have.
+        let hidden = entry.file_name().to_string_lossy().starts_with('.');
+        if !hidden || entry.depth() == 1 {
+            directories.push(entry.path().to_path_buf());
+        }
+        if hidden || stop(entry.path()) {
+            entries.skip_current_dir();
+        }
+    }
+    Ok(directories)
+}
+
+/// Reads and parses a manifest at the root, if it exists.
+fn read_manifest<T>(
+    root: &Path,
+    name: &str,
+    parse: impl Fn(&str) -> Result<T, String>,
+) -> Result<Option<T>, LoopError> {
+    let path = root.join(name);
+    let text = match fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => {
+            return Err(LoopError::io(
+                format!("failed to read {}", path.display()),
+                err,
+            ))
+        }
+    };
+    parse(&text)
+        .map(Some)
+        .map_err(|message| LoopError::Workspace { path, message })
+}
+
+fn string_list<T: serde::Serialize>(value: Option<&T>) -> Vec<String> {
+    value
+        .and_then(|value| serde_json::to_value(value).ok())
+        .and_then(|value| serde_json::from_value(value).ok())
+        .unwrap_or_default()
+}
+
+/// Expands patterns in which a leading `!` excludes the matching directories, as in
+/// `package.json` and `pnpm-workspace.yaml`.
+fn expand_negated(root: &Path, patterns: &[String]) -> Result<Vec<PathBuf>, LoopError> {
+    let (exclude, members): (Vec<String>, Vec<String>) = patterns
+        .iter()
+        .cloned()
+        .partition(|pattern| pattern.starts_with('!'));
+    let exclude: Vec<String> = exclude
+        .iter()
+        .map(|pattern| pattern[1..].to_string())
+        .collect();
+    expand_members(root, &members, &exclude)
+}
+
+/// Expands member patterns relative to the root into the matching directories, in
+/// pattern order and then name order, leaving out those matching an exclude pattern.
+fn expand_members(
+    root: &Path,
+    members: &[String],
+    exclude: &[String],
+) -> Result<Vec<PathBuf>, LoopError> {
+    let excluded = expand(root, exclude)?;
+    Ok(expand(root, members)?
+        .into_iter()
+        .filter(|dir| !excluded.contains(dir))
+        .collect())
+}
+
+fn expand(root: &Path, patterns: &[String]) -> Result<Vec<PathBuf>, LoopError> {
+    let mut directories = Vec::new();
+    for pattern in patterns {
+        let full = root.join(pattern.trim_start_matches("./"));
+        let matches = glob::glob(&full.to_string_lossy()).map_err(|err| LoopError::Workspace {
+            path: root.to_path_buf(),
+            message: format!("invalid member pattern {}: {}", pattern, err),
+        })?;
+        let mut matched: Vec<PathBuf> = matches
+            .filter_map(Result::ok)
+            .filter(|path| path.is_dir())
+            .collect();
+        matched.sort();
+        directories.extend(matched);
+    }
+    Ok(directories)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn names(root: &Path, dirs: Vec<PathBuf>) -> Vec<String> {
+        dirs.iter()
+            .map(|dir| {
+                dir.strip_prefix(root)
+                    .unwrap()
+                    .to_string_lossy()
+                    .into_owned()
+            })
+            .collect()
+    }
+
+    fn create_dirs(root: &Path, dirs: &[&str]) {
+        for dir in dirs {
+            fs::create_dir_all(root.join(dir)).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_children_and_recursive() {
+        let temp_dir = tempdir().unwrap();
+        let root = temp_dir.path();
+        create_dirs(root, &["b/inner/deep", "a", ".hidden/inner", "b/.cache"]);
+
+        assert_eq!(
+            names(root, Children.directories(root).unwrap()),
+            vec![".hidden", "a", "b"]
+        );
+        assert_eq!(
+            names(root, Recursive::default().directories(root).unwrap()),
+            vec![".hidden", "a", "b", "b/inner", "b/inner/deep"]
+        );
+        let shallow = Recursive { max_depth: Some(2) };
+        assert_eq!(
+            names(root, shallow.directories(root).unwrap()),
+            vec![".hidden", "a", "b", "b/inner"]
+        );
+    }
+
+    #[test]
+    fn test_pruned_directories() {
+        let temp_dir = tempdir().unwrap();
+        let root = temp_dir.path();
+        create_dirs(
+            root,
+            &["web/node_modules/pkg/.git", "web/src", "target/debug/.git"],
+        );
+        let prune = |dir: &Path| dir.ends_with("node_modules") || dir.ends_with("target");
+
+        assert_eq!(
+            names(
+                root,
+                Recursive::default()
+                    .pruned_directories(root, &prune)
+                    .unwrap()
+            ),
+            vec!["target", "web", "web/node_modules", "web/src"]
+        );
+        let sources: Vec<Box<dyn DirectorySource>> = vec![Box::new(GitRepositories::default())];
+        assert!(sources.pruned_directories(root, &prune).unwrap().is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_unreadable_directories_are_skipped() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp_dir = tempdir().unwrap();
+        let root = temp_dir.path();
+        create_dirs(root, &["locked/inner", "repo/.git"]);
+        fs::set_permissions(root.join("locked"), fs::Permissions::from_mode(0o000)).unwrap();
+
+        let recursive = Recursive::default().directories(root);
+        let repositories = GitRepositories::default().directories(root);
+        fs::set_permissions(root.join("locked"), fs::Permissions::from_mode(0o755)).unwrap();
+
+        let recursive = names(root, recursive.unwrap());
+        assert!(recursive.contains(&"locked".to_string()));
+        assert!(recursive.contains(&"repo".to_string()));
+        assert_eq!(names(root, repositories.unwrap()), vec!["repo"]);
+        assert!(Recursive::default()
+            .directories(&root.join("missing"))
+            .is_err());
+    }
+
+    #[test]
+    fn test_git_repositories() {
+        let temp_dir = tempdir().unwrap();
+        let root = temp_dir.path();
+        create_dirs(
+            root,
+            &["api/.git", "api/vendor/lib/.git", "libs/core/.git", "docs"],
+        );
+        fs::create_dir_all(root.join("libs/worktree")).unwrap();
+        fs::write(root.join("libs/worktree/.git"), "gitdir: elsewhere").unwrap();
+
+        assert_eq!(
+            names(root, GitRepositories::default().directories(root).unwrap()),
+            vec!["api", "libs/core", "libs/worktree"]
+        );
+    }
+
+    #[test]
+    fn test_directory_list_and_composition() {
+        let temp_dir = tempdir().unwrap();
+        let root = temp_dir.path();
+        create_dirs(root, &["a", "b"]);
+
+        let sources: Vec<Box<dyn DirectorySource>> = vec![
+            Box::new(DirectoryList(vec!["b".into(), "missing".into()])),
+            Box::new(Children),
+        ];
+        assert_eq!(
+            names(root, sources.directories(root).unwrap()),
+            vec!["b", "missing", "a", "b"]
+        );
+    }
+
+    #[test]
+    fn test_workspace_members() {
+        let temp_dir = tempdir().unwrap();
+        let root = temp_dir.path();
+        create_dirs(
+            root,
+            &[
+                "crates/core",
+                "crates/cli",
+                "crates/old",
+                "tools",
+                "web/app",
+                "web/legacy",
+            ],
+        );
+        fs::write(
+            root.join("Cargo.toml"),
+            "[workspace]\nmembers = [\"crates/*\", \"tools\"]\nexclude = [\"crates/old\"]\n",
+        )
+        .unwrap();
+        fs::write(
+            root.join("package.json"),
+            r#"{"workspaces": {"packages": ["web/*", "!web/legacy"]}}"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            names(root, WorkspaceMembers.directories(root).unwrap()),
+            vec!["crates/cli", "crates/core", "tools", "web/app"]
+        );
+    }
+
+    #[test]
+    fn test_workspace_invalid_manifest() {
+        let temp_dir = tempdir().unwrap();
+        fs::write(temp_dir.path().join("package.json"), "{").unwrap();
+
+        let err = WorkspaceMembers.directories(temp_dir.path()).unwrap_err();
+        assert!(matches!(err, LoopError::Workspace { .. }));
+        assert_eq!(err.exit_code(), exitcode::CONFIG);
+    }
+}
@@ -0,0 +1,172 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// The placeholder values available to a command run in one directory.
+///
+/// The built-in placeholders are `{name}`, `{path}`, `{abspath}`, `{index}` (one-based)
+/// and `{total}`. Variables from the `variables` section of the configuration are
+/// available under their own names, but cannot shadow the built-ins.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TemplateContext {
+    values: BTreeMap<String, String>,
+}
+
+impl TemplateContext {
+    /// Builds the placeholder values for a directory. `{path}` is relative to `root`
+    /// when the directory is inside it.
+    pub fn new(
+        dir: &Path,
+        root: &Path,
+        index: usize,
+        total: usize,
+        variables: &BTreeMap<String, String>,
+    ) -> TemplateContext {
+        let mut values = variables.clone();
+        let path = dir.strip_prefix(root).unwrap_or(dir);
+        let abspath = dir
+            .canonicalize()
+            .or_else(|_| std::path::absolute(dir))
+            .unwrap_or_else(|_| dir.to_path_buf());
+
+        values.insert(
+            "name".to_string(),
+            dir.file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .into_owned(),
+        );
+        values.insert("path".to_string(), path.to_string_lossy().into_owned());
+        values.insert(
+            "abspath".to_string(),
+            abspath.to_string_lossy().into_owned(),
+        );
+        values.insert("index".to_string(), index.to_string());
+        values.insert("total".to_string(), total.to_string());
+
+        TemplateContext { values }
+    }
+
+    /// Looks up the value of a placeholder.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.values.get(name).map(String::as_str)
+    }
+}
+
+/// Replaces `{placeholder}`s in the template with values from the context.
+///
+/// Each value is passed through `quote` before it is inserted, which lets callers
+/// shell-quote values substituted into shell code. `{{placeholder}}` produces the literal
+/// text `{placeholder}`. All other braces, such as shell brace expansion, `${A:-${B}}` or
+/// Go templates like `{{.Names}}`, are left untouched.
+pub fn render(template: &str, context: &TemplateContext, quote: impl Fn(&str) -> String) -> String {
+    let mut result = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(index) = rest.find('{') {
+        result.push_str(&rest[..index]);
+        rest = &rest[index..];
+
+        if let Some(name) = placeholder(&rest[1..], context).filter(|_| rest.starts_with("{{")) {
+            if rest[name.len() + 2..].starts_with("}}") {
+                result.push_str(&rest[1..name.len() + 3]);
+                rest = &rest[name.len() + 4..];
+                continue;
+            }
+        }
+
+        match placeholder(rest, context) {
+            Some(name) => {
+                result.push_str(&quote(context.get(name).unwrap_or_default()));
+                rest = &rest[name.len() + 2..];
+            }
+            None => {
+                result.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+
+    result.push_str(rest);
+    result
+}
+
+/// Returns the name of the known placeholder that `text` starts with, if any.
+fn placeholder<'a>(text: &'a str, context: &TemplateContext) -> Option<&'a str> {
+    let after = text.strip_prefix('{')?;
+    let name = &after[..after.find(['{', '}'])?];
+    (after[name.len()..].starts_with('}') && context.get(name).is_some()).then_some(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn context() -> TemplateContext {
+        let mut variables = BTreeMap::new();
+        variables.insert("backups".to_string(), "/var/backups".to_string());
+        variables.insert("name".to_string(), "shadowed".to_string());
+        TemplateContext::new(Path::new("./my repo"), Path::new("."), 2, 5, &variables)
+    }
+
+    #[test]
+    fn test_context_values() {
+        let context = context();
+        assert_eq!(context.get("name"), Some("my repo"));
+        assert_eq!(context.get("path"), Some("my repo"));
+        assert_eq!(context.get("index"), Some("2"));
+        assert_eq!(context.get("total"), Some("5"));
+        assert_eq!(context.get("backups"), Some("/var/backups"));
+        assert!(Path::new(context.get("abspath").unwrap()).is_absolute());
+
+        let context = TemplateContext::new(
+            Path::new("/srv/repos/api"),
+            Path::new("/srv/repos"),
+            1,
+            1,
+            &BTreeMap::new(),
+        );
+        assert_eq!(context.get("path"), Some("api"));
+    }
+
+    #[test]
+    fn test_render() {
+        let context = context();
+        let raw = |value: &str| value.to_string();
+
+        assert_eq!(
+            render("{backups}/{name}.tgz", &context, raw),
+            "/var/backups/my repo.tgz"
+        );
+        assert_eq!(render("{index}/{total}", &context, raw), "2/5");
+        assert_eq!(render("{{name}}", &context, raw), "{name}");
+        assert_eq!(render("{{{name}}}", &context, raw), "{{name}}");
+        assert_eq!(render("{ {name} }", &context, raw), "{ my repo }");
+        assert_eq!(
+            render("echo ${A:-${B}} }} {{", &context, raw),
+            "echo ${A:-${B}} }} {{"
+        );
+        assert_eq!(
+            render("docker ps --format '{{.Names}}'", &context, raw),
+            "docker ps --format '{{.Names}}'"
+        );
+        assert_eq!(
+            render("{unknown} {a,b} ${HOME} {}", &context, raw),
+            "{unknown} {a,b} ${HOME} {}"
+        );
+        assert_eq!(
+            render("awk '{print $1}'", &context, raw),
+            "awk '{print $1}'"
+        );
+        assert_eq!(render("{name", &context, raw), "{name");
+        assert_eq!(render("{{ {x{name}", &context, raw), "{{ {xmy repo");
+    }
+
+    #[test]
+    fn test_render_quotes_values() {
+        let quote = |value: &str| format!("'{}'", value);
+        assert_eq!(
+            render("tar czf {name}.tgz .", &context(), quote),
+            "tar czf 'my repo'.tgz ."
+        );
+    }
+}
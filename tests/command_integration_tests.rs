@@ -1,29 +1,145 @@
 use assert_cmd::Command;
-use once_cell::sync::Lazy;
 use predicates::prelude::*;
 use std::fs;
-use std::sync::Mutex;
+use std::path::{Path, PathBuf};
 use tempfile::tempdir;
 
-// Create a global mutex
-static CURRENT_DIR_MUTEX: Lazy<Mutex<()>> = Lazy::new(|| Mutex::new(()));
+/// The cache directory the tests run `loop` with, so that they never write to the
+/// user's.
+fn cache_dir() -> PathBuf {
+    Path::new(env!("CARGO_TARGET_TMPDIR")).join("cache")
+}
+
+fn loop_command() -> Command {
+    let mut cmd = Command::cargo_bin("loop").unwrap();
+    cmd.env("XDG_CACHE_HOME", cache_dir());
+    cmd
+}
 
 #[test]
 fn test_init_creates_looprc() {
     let temp_dir = tempdir().unwrap();
-    let mut cmd = Command::cargo_bin("loop").unwrap();
+    let mut cmd = loop_command();
     cmd.current_dir(&temp_dir).arg("--init").assert().success();
 
     assert!(temp_dir.path().join(".looprc").exists());
 }
 
+#[test]
+fn test_init_refuses_to_overwrite() {
+    let temp_dir = tempdir().unwrap();
+    fs::write(temp_dir.path().join(".looprc"), "{\"ignore\": [\"keep\"]}").unwrap();
+
+    let mut cmd = loop_command();
+    cmd.current_dir(&temp_dir)
+        .arg("--init")
+        .assert()
+        .code(exitcode::CANTCREAT)
+        .stderr(predicate::str::contains("--force"));
+    assert!(fs::read_to_string(temp_dir.path().join(".looprc"))
+        .unwrap()
+        .contains("keep"));
+
+    let mut cmd = loop_command();
+    cmd.current_dir(&temp_dir)
+        .args(["--init", "--force"])
+        .assert()
+        .success();
+    assert!(!fs::read_to_string(temp_dir.path().join(".looprc"))
+        .unwrap()
+        .contains("keep"));
+}
+
+#[test]
+fn test_init_detects_projects_and_groups_run() {
+    let temp_dir = tempdir().unwrap();
+    fs::create_dir_all(temp_dir.path().join("api/.git")).unwrap();
+    fs::write(temp_dir.path().join("api/Cargo.toml"), "").unwrap();
+    fs::create_dir(temp_dir.path().join("web")).unwrap();
+    fs::write(temp_dir.path().join("web/package.json"), "{}").unwrap();
+    fs::create_dir(temp_dir.path().join("node_modules")).unwrap();
+
+    let mut cmd = loop_command();
+    cmd.current_dir(&temp_dir).arg("--init").assert().success();
+
+    let content = fs::read_to_string(temp_dir.path().join(".looprc")).unwrap();
+    assert!(content.contains("node_modules"));
+    assert!(content.contains("\"rust\""));
+
+    let mut cmd = loop_command();
+    cmd.current_dir(&temp_dir)
+        .args(["--group", "rust", "--", "pwd"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("api"))
+        .stdout(predicate::str::contains("web").not());
+
+    let mut cmd = loop_command();
+    cmd.current_dir(&temp_dir)
+        .args(["--group", "missing", "--", "pwd"])
+        .assert()
+        .code(exitcode::USAGE);
+}
+
+#[test]
+fn test_alias_is_expanded() {
+    let temp_dir = tempdir().unwrap();
+    fs::create_dir(temp_dir.path().join("dir1")).unwrap();
+    fs::write(
+        temp_dir.path().join(".looprc.toml"),
+        "[aliases]\ngreet = \"echo hello from alias\"\n",
+    )
+    .unwrap();
+
+    let mut cmd = loop_command();
+    cmd.current_dir(&temp_dir)
+        .args(["greet", "and", "args"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("hello from alias and args"));
+}
+
+#[test]
+fn test_init_with_toml_format() {
+    let temp_dir = tempdir().unwrap();
+    let mut cmd = loop_command();
+    cmd.current_dir(&temp_dir)
+        .args(["--init", "--format", "toml"])
+        .assert()
+        .success();
+
+    let content = fs::read_to_string(temp_dir.path().join(".looprc.toml")).unwrap();
+    assert!(content.contains("ignore = [\".git\"]"));
+}
+
+#[test]
+fn test_yaml_config_is_applied() {
+    let temp_dir = tempdir().unwrap();
+    fs::create_dir(temp_dir.path().join("kept_dir")).unwrap();
+    fs::create_dir(temp_dir.path().join("ignored_dir")).unwrap();
+    fs::write(
+        temp_dir.path().join(".looprc.yaml"),
+        "# directories to skip\nignore:\n  - ignored_dir\n",
+    )
+    .unwrap();
+
+    let mut cmd = loop_command();
+    cmd.current_dir(&temp_dir)
+        .arg("echo")
+        .arg("test")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("kept_dir"))
+        .stdout(predicate::str::contains("ignored_dir").not());
+}
+
 #[test]
 fn test_execute_in_child_directories() {
     let temp_dir = tempdir().unwrap();
     fs::create_dir(temp_dir.path().join("dir1")).unwrap();
     fs::create_dir(temp_dir.path().join("dir2")).unwrap();
 
-    let mut cmd = Command::cargo_bin("loop").unwrap();
+    let mut cmd = loop_command();
     cmd.current_dir(&temp_dir)
         .arg("echo")
         .arg("test")
@@ -38,7 +154,7 @@ fn test_include_directory() {
     let temp_dir = tempdir().unwrap();
     fs::create_dir(temp_dir.path().join("include_dir")).unwrap();
 
-    let mut cmd = Command::cargo_bin("loop").unwrap();
+    let mut cmd = loop_command();
     cmd.current_dir(&temp_dir)
         .arg("echo")
         .arg("test")
@@ -55,7 +171,7 @@ fn test_exclude_directory() {
     fs::create_dir(temp_dir.path().join("include_dir")).unwrap();
     fs::create_dir(temp_dir.path().join("exclude_dir")).unwrap();
 
-    let mut cmd = Command::cargo_bin("loop").unwrap();
+    let mut cmd = loop_command();
     cmd.current_dir(&temp_dir)
         .arg("echo")
         .arg("test")
@@ -67,25 +183,754 @@ fn test_exclude_directory() {
         .stdout(predicate::str::contains("exclude_dir").not());
 }
 
+#[test]
+fn test_invalid_config_exits_with_error() {
+    let temp_dir = tempdir().unwrap();
+    fs::create_dir(temp_dir.path().join("dir1")).unwrap();
+    fs::write(temp_dir.path().join(".looprc"), "{\n  \"ignroe\": []\n}").unwrap();
+
+    let mut cmd = loop_command();
+    cmd.current_dir(&temp_dir)
+        .arg("echo")
+        .arg("test")
+        .assert()
+        .code(exitcode::CONFIG)
+        .stderr(predicate::str::contains(".looprc:2:"))
+        .stderr(predicate::str::contains("unknown field `ignroe`"))
+        .stdout(predicate::str::contains("dir1").not());
+}
+
+#[test]
+fn test_allow_invalid_config_continues() {
+    let temp_dir = tempdir().unwrap();
+    fs::create_dir(temp_dir.path().join("dir1")).unwrap();
+    fs::write(temp_dir.path().join(".looprc"), "{\"ignroe\": []}").unwrap();
+
+    let mut cmd = loop_command();
+    cmd.current_dir(&temp_dir)
+        .args(["echo", "test", "--allow-invalid-config"])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("Warning"))
+        .stdout(predicate::str::contains("dir1"));
+}
+
+#[test]
+fn test_config_validate() {
+    let temp_dir = tempdir().unwrap();
+    fs::write(
+        temp_dir.path().join(".looprc.toml"),
+        "ignore = [\".git\"]\n",
+    )
+    .unwrap();
+
+    let mut cmd = loop_command();
+    cmd.current_dir(&temp_dir)
+        .args(["config", "validate"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("OK"));
+
+    fs::write(temp_dir.path().join(".looprc.toml"), "ignore = \".git\"\n").unwrap();
+
+    let mut cmd = loop_command();
+    cmd.current_dir(&temp_dir)
+        .args(["config", "validate"])
+        .assert()
+        .code(exitcode::CONFIG)
+        .stderr(predicate::str::contains(".looprc.toml:1:10:"));
+}
+
+#[test]
+fn test_config_show_reports_sources() {
+    let temp_dir = tempdir().unwrap();
+    fs::write(
+        temp_dir.path().join(".looprc"),
+        "{\"ignore\": [\"target\"]}",
+    )
+    .unwrap();
+
+    let mut cmd = loop_command();
+    cmd.current_dir(&temp_dir)
+        .args(["config", "show", "--exclude", "node_modules"])
+        .assert()
+        .success()
+        .stdout(predicate::str::is_match(r#"ignore\s+\["target"\]\s+\./\.looprc"#).unwrap())
+        .stdout(
+            predicate::str::is_match(
+                r#"exclude\s+\["node_modules"\]\s+command line \(--exclude\)"#,
+            )
+            .unwrap(),
+        )
+        .stdout(predicate::str::is_match(r"include\s+null\s+default").unwrap())
+        .stdout(predicate::str::is_match(r#"sort\s+"none"\s+default"#).unwrap());
+}
+
+#[test]
+fn test_config_show_reports_effective_values() {
+    let temp_dir = tempdir().unwrap();
+    fs::write(
+        temp_dir.path().join(".looprc"),
+        "{\"no_rc\": true, \"dotenv\": true}",
+    )
+    .unwrap();
+
+    let mut cmd = loop_command();
+    let output = cmd
+        .current_dir(&temp_dir)
+        .args(["config", "show", "--rc", "--no-shell", "--sort", "name"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(stdout.matches("\nno_rc ").count(), 1);
+    assert_eq!(stdout.matches("\ndotenv ").count(), 1);
+    assert!(
+        predicate::str::is_match(r"no_rc\s+false\s+command line \(--rc\)")
+            .unwrap()
+            .eval(&stdout)
+    );
+    assert!(predicate::str::is_match(r"dotenv\s+true\s+\./\.looprc")
+        .unwrap()
+        .eval(&stdout));
+    assert!(
+        predicate::str::is_match(r"no_shell\s+true\s+command line \(--no-shell\)")
+            .unwrap()
+            .eval(&stdout)
+    );
+    assert!(
+        predicate::str::is_match(r#"sort\s+"name"\s+command line \(--sort\)"#)
+            .unwrap()
+            .eval(&stdout)
+    );
+}
+
+#[test]
+fn test_config_set_and_get() {
+    let temp_dir = tempdir().unwrap();
+
+    let mut cmd = loop_command();
+    cmd.current_dir(&temp_dir)
+        .args(["config", "set", "ignore", ".git", "target"])
+        .assert()
+        .success();
+
+    let mut cmd = loop_command();
+    cmd.current_dir(&temp_dir)
+        .args(["config", "get", "ignore"])
+        .assert()
+        .success()
+        .stdout("[\".git\",\"target\"]\n");
+
+    // Only the key that was set is written, so the others keep their defaults
+    let mut cmd = loop_command();
+    cmd.current_dir(&temp_dir)
+        .args(["config", "show", "--no-rc"])
+        .assert()
+        .success()
+        .stdout(predicate::str::is_match(r#"exit_status\s+"first"\s+default"#).unwrap())
+        .stdout(predicate::str::is_match(r"groups\s+\{\}\s+default").unwrap());
+
+    let mut cmd = loop_command();
+    cmd.current_dir(&temp_dir)
+        .args(["config", "set", "ignroe", "target"])
+        .assert()
+        .code(exitcode::CONFIG)
+        .stderr(predicate::str::contains("unknown field `ignroe`"));
+
+    let mut cmd = loop_command();
+    cmd.current_dir(&temp_dir)
+        .args(["config", "set", "env.PORT", "8080"])
+        .assert()
+        .success();
+    let mut cmd = loop_command();
+    cmd.current_dir(&temp_dir)
+        .args(["config", "get", "env.PORT"])
+        .assert()
+        .success()
+        .stdout("\"8080\"\n");
+}
+
+#[test]
+fn test_config_interpolates_environment() {
+    let temp_dir = tempdir().unwrap();
+    for dir in ["kept_dir", "from_env", "from_default"] {
+        fs::create_dir(temp_dir.path().join(dir)).unwrap();
+    }
+    fs::write(
+        temp_dir.path().join(".looprc.toml"),
+        "ignore = [\"${LOOP_TEST_IGNORE}\", \"${LOOP_TEST_UNSET:-from_default}\"]\n",
+    )
+    .unwrap();
+
+    let mut cmd = loop_command();
+    cmd.current_dir(&temp_dir)
+        .env("LOOP_TEST_IGNORE", "from_env")
+        .env_remove("LOOP_TEST_UNSET")
+        .args(["echo", "test"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("kept_dir"))
+        .stdout(predicate::str::contains("from_env").not())
+        .stdout(predicate::str::contains("from_default").not());
+}
+
+#[test]
+fn test_include_and_cwd_expand_tilde() {
+    let home = tempdir().unwrap();
+    fs::create_dir_all(home.path().join("work/child")).unwrap();
+    fs::create_dir(home.path().join("bootstrap")).unwrap();
+
+    let mut cmd = loop_command();
+    cmd.env("HOME", home.path())
+        .args(["--cwd", "~/work", "--include", "~/bootstrap", "--", "pwd"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("child ✓"))
+        .stdout(predicate::str::contains("bootstrap ✓"));
+}
+
+#[test]
+fn test_no_shell_passes_arguments_verbatim() {
+    let temp_dir = tempdir().unwrap();
+    fs::create_dir(temp_dir.path().join("dir1")).unwrap();
+
+    let mut cmd = loop_command();
+    cmd.current_dir(&temp_dir)
+        .args([
+            "--no-shell",
+            "--",
+            "printf",
+            "[%s]\\n",
+            "$HOME",
+            "two words",
+        ])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("[$HOME]"))
+        .stdout(predicate::str::contains("[two words]"));
+}
+
+#[test]
+fn test_shell_override() {
+    let temp_dir = tempdir().unwrap();
+    fs::create_dir(temp_dir.path().join("dir1")).unwrap();
+
+    let mut cmd = loop_command();
+    cmd.current_dir(&temp_dir)
+        .env("SHELL", "/nonexistent/shell")
+        .args(["--shell", "/bin/sh", "--", "echo", "from sh"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("from sh"));
+}
+
+#[test]
+fn test_no_rc_skips_rc_file() {
+    let home = tempdir().unwrap();
+    fs::write(home.path().join(".bashrc"), "echo RC LOADED\n").unwrap();
+    let temp_dir = tempdir().unwrap();
+    fs::create_dir(temp_dir.path().join("dir1")).unwrap();
+
+    let mut cmd = loop_command();
+    cmd.current_dir(&temp_dir)
+        .env("HOME", home.path())
+        .args(["--shell", "/bin/sh", "--", "echo", "test"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("RC LOADED"));
+
+    let mut cmd = loop_command();
+    cmd.current_dir(&temp_dir)
+        .env("HOME", home.path())
+        .args(["--shell", "/bin/sh", "--no-rc", "--", "echo", "test"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("RC LOADED").not());
+
+    fs::write(temp_dir.path().join(".looprc"), "{\"no_rc\": true}").unwrap();
+
+    let mut cmd = loop_command();
+    cmd.current_dir(&temp_dir)
+        .env("HOME", home.path())
+        .args(["--shell", "/bin/sh", "--", "echo", "test"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("RC LOADED").not());
+
+    let mut cmd = loop_command();
+    cmd.current_dir(&temp_dir)
+        .env("HOME", home.path())
+        .args(["--shell", "/bin/sh", "--rc", "--", "echo", "test"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("RC LOADED"));
+}
+
+#[test]
+fn test_arguments_are_quoted_for_the_shell() {
+    let temp_dir = tempdir().unwrap();
+    fs::create_dir(temp_dir.path().join("dir1")).unwrap();
+
+    for shell in ["/bin/sh", "/bin/bash"] {
+        let mut cmd = loop_command();
+        cmd.current_dir(&temp_dir)
+            .args(["--shell", shell, "--no-rc", "--"])
+            .args(["printf", "[%s]\\n", "two words", "$HOME", "it's", "`id`"])
+            .assert()
+            .success()
+            .stdout(predicate::str::contains(
+                "[two words]\n[$HOME]\n[it's]\n[`id`]\n",
+            ));
+    }
+}
+
+#[test]
+fn test_command_placeholders() {
+    let temp_dir = tempdir().unwrap();
+    fs::create_dir(temp_dir.path().join("alpha")).unwrap();
+    fs::create_dir(temp_dir.path().join("beta dir")).unwrap();
+    fs::write(
+        temp_dir.path().join(".looprc.toml"),
+        "[variables]\nsuffix = \"bak\"\n",
+    )
+    .unwrap();
+
+    let mut cmd = loop_command();
+    cmd.current_dir(&temp_dir)
+        .args(["--no-rc", "echo [{index}/{total}] {name}.{suffix} {{name}}"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("[1/2] alpha.bak {name}"))
+        .stdout(predicate::str::contains("[2/2] beta dir.bak {name}"));
+
+    let mut cmd = loop_command();
+    cmd.current_dir(&temp_dir)
+        .args(["--no-rc", "--", "touch", "../{name}.{suffix}"])
+        .assert()
+        .success();
+    assert!(temp_dir.path().join("beta dir.bak").exists());
+}
+
+#[test]
+fn test_braces_without_placeholders_are_left_alone() {
+    let temp_dir = tempdir().unwrap();
+    fs::create_dir(temp_dir.path().join("dir1")).unwrap();
+
+    let mut cmd = loop_command();
+    cmd.current_dir(&temp_dir)
+        .env_remove("A")
+        .env("B", "from-b")
+        .args(["--no-rc", "--shell", "/bin/bash", "echo ${A:-${B}}"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("from-b"));
+
+    let mut cmd = loop_command();
+    cmd.current_dir(&temp_dir)
+        .args(["--no-rc", "--shell", "/bin/sh", "--", "echo", "{{.Names}}"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("{{.Names}}"));
+
+    let mut cmd = loop_command();
+    cmd.current_dir(&temp_dir)
+        .args([
+            "--no-shell",
+            "--",
+            "printf",
+            "[%s]\\n",
+            "{{.Names}}",
+            "${A:-${B}}",
+        ])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("[{{.Names}}]\n[${A:-${B}}]\n"));
+}
+
+#[test]
+fn test_loop_environment_variables() {
+    let temp_dir = tempdir().unwrap();
+    fs::create_dir(temp_dir.path().join("alpha")).unwrap();
+    fs::create_dir(temp_dir.path().join("beta")).unwrap();
+    let root = temp_dir.path().canonicalize().unwrap();
+
+    let output = loop_command()
+        .current_dir(&root)
+        .args([
+            "--no-rc",
+            "echo \"env $LOOP_INDEX/$LOOP_TOTAL $LOOP_DIR_NAME $LOOP_DIR $LOOP_ROOT run=$LOOP_RUN_ID\"",
+        ])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+
+    let lines: Vec<&str> = stdout
+        .lines()
+        .filter(|line| line.starts_with("env "))
+        .collect();
+    assert_eq!(
+        lines[0].split(" run=").next().unwrap(),
+        format!(
+            "env 1/2 alpha {} {}",
+            root.join("alpha").display(),
+            root.display()
+        )
+    );
+    assert!(lines[1].starts_with("env 2/2 beta "));
+
+    let run_ids: Vec<&str> = lines
+        .iter()
+        .map(|line| line.split(" run=").nth(1).unwrap())
+        .collect();
+    assert!(!run_ids[0].is_empty());
+    assert_eq!(run_ids[0], run_ids[1]);
+}
+
+#[test]
+fn test_per_directory_environment() {
+    let temp_dir = tempdir().unwrap();
+    fs::create_dir(temp_dir.path().join("api")).unwrap();
+    fs::create_dir(temp_dir.path().join("web")).unwrap();
+    fs::write(temp_dir.path().join("web/.env"), "STAGE=from_dotenv\n").unwrap();
+    fs::write(
+        temp_dir.path().join(".looprc.toml"),
+        "[env]\nSTAGE = \"global\"\n\n[dir_env.api]\nSTAGE = \"api\"\n",
+    )
+    .unwrap();
+
+    loop_command()
+        .current_dir(temp_dir.path())
+        .args([
+            "--no-rc",
+            "--dotenv",
+            "echo \"stage $LOOP_DIR_NAME=$STAGE\"",
+        ])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("stage api=api"))
+        .stdout(predicate::str::contains("stage web=from_dotenv"));
+
+    loop_command()
+        .current_dir(temp_dir.path())
+        .args(["--no-rc", "echo \"stage $LOOP_DIR_NAME=$STAGE\""])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("stage web=global"));
+
+    loop_command()
+        .current_dir(temp_dir.path())
+        .args(["config", "env", "web", "--dotenv"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("STAGE=from_dotenv"))
+        .stdout(predicate::str::contains(".env"));
+}
+
+#[test]
+fn test_script_runs_in_each_directory() {
+    let temp_dir = tempdir().unwrap();
+    fs::create_dir(temp_dir.path().join("alpha")).unwrap();
+    fs::create_dir(temp_dir.path().join("beta")).unwrap();
+    fs::create_dir(temp_dir.path().join("scripts")).unwrap();
+    fs::write(
+        temp_dir.path().join("scripts/update.sh"),
+        "name=$(basename \"$PWD\")\necho \"updated $name with $1\"\n",
+    )
+    .unwrap();
+    fs::write(
+        temp_dir.path().join(".looprc.toml"),
+        "ignore = [\"scripts\"]\n\n[scripts]\nupdate = \"scripts/update.sh\"\n",
+    )
+    .unwrap();
+
+    loop_command()
+        .current_dir(temp_dir.path())
+        .args([
+            "--no-rc",
+            "--script",
+            "./scripts/update.sh",
+            "--",
+            "{name}-arg",
+        ])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("updated alpha with alpha-arg"))
+        .stdout(predicate::str::contains("updated beta with beta-arg"));
+
+    loop_command()
+        .args([
+            "-C",
+            temp_dir.path().to_str().unwrap(),
+            "--script",
+            "update",
+        ])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("updated alpha with"));
+
+    loop_command()
+        .current_dir(temp_dir.path())
+        .args(["--script", "missing.sh"])
+        .assert()
+        .code(exitcode::NOINPUT)
+        .stderr(predicate::str::contains("Script not found"));
+}
+
+#[test]
+fn test_pipeline_steps() {
+    let temp_dir = tempdir().unwrap();
+    fs::create_dir(temp_dir.path().join("alpha")).unwrap();
+    fs::create_dir(temp_dir.path().join("beta")).unwrap();
+    fs::write(temp_dir.path().join("beta/broken"), "").unwrap();
+
+    loop_command()
+        .current_dir(temp_dir.path())
+        .args([
+            "--no-rc",
+            "--step",
+            "echo pulled {name}",
+            "--step",
+            "-test ! -e optional",
+            "--step",
+            "test ! -e broken",
+            "--step",
+            "touch tested",
+        ])
+        .assert()
+        .code(1)
+        .stdout(predicate::str::contains("pulled alpha"))
+        .stdout(predicate::str::contains("pulled beta"))
+        .stdout(predicate::str::contains("alpha ✓: 4 steps"))
+        .stdout(predicate::str::contains("beta › touch tested skipped"))
+        .stdout(predicate::str::contains(
+            "beta ✗: failed at step 3 of 4 (test ! -e broken)",
+        ));
+    assert!(temp_dir.path().join("alpha/tested").exists());
+    assert!(!temp_dir.path().join("beta/tested").exists());
+
+    fs::write(
+        temp_dir.path().join(".looprc.yaml"),
+        "pipelines:\n  check:\n    - name: broken\n      run: test ! -e broken\n      continue_on_error: true\n    - touch checked\n",
+    )
+    .unwrap();
+
+    loop_command()
+        .current_dir(temp_dir.path())
+        .args(["--no-rc", "--pipeline", "check"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("beta › broken ✗"))
+        .stdout(predicate::str::contains(
+            "beta ✓: 2 steps, 1 failure ignored",
+        ));
+    assert!(temp_dir.path().join("beta/checked").exists());
+
+    loop_command()
+        .current_dir(temp_dir.path())
+        .args(["--pipeline", "missing"])
+        .assert()
+        .code(exitcode::USAGE);
+}
+
+/// Starts loop running `sleep` in two directories, sends it a signal once the first
+/// command is running, and returns the exit code, stderr and how long loop took.
+#[cfg(unix)]
+fn interrupt_loop(signal: i32) -> (Option<i32>, String, std::time::Duration) {
+    use std::process::Stdio;
+    use std::time::{Duration, Instant};
+
+    let temp_dir = tempdir().unwrap();
+    fs::create_dir(temp_dir.path().join("alpha")).unwrap();
+    fs::create_dir(temp_dir.path().join("beta")).unwrap();
+
+    let started = Instant::now();
+    let child = std::process::Command::new(assert_cmd::cargo::cargo_bin("loop"))
+        .current_dir(temp_dir.path())
+        .env("XDG_CACHE_HOME", cache_dir())
+        .args(["--no-rc", "sleep 10"])
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    std::thread::sleep(Duration::from_millis(500));
+    unsafe { libc::kill(child.id() as libc::pid_t, signal) };
+
+    let output = child.wait_with_output().unwrap();
+    (
+        output.status.code(),
+        String::from_utf8(output.stderr).unwrap(),
+        started.elapsed(),
+    )
+}
+
+#[cfg(unix)]
+#[test]
+fn test_sigterm_stops_the_loop() {
+    let (code, stderr, elapsed) = interrupt_loop(libc::SIGTERM);
+    assert_eq!(code, Some(143));
+    assert!(elapsed < std::time::Duration::from_secs(8));
+    assert!(
+        stderr.contains("Interrupted by SIGTERM: 1 of 2 directories run, 1 failed"),
+        "{}",
+        stderr
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_sigint_stops_the_loop() {
+    let (code, stderr, elapsed) = interrupt_loop(libc::SIGINT);
+    assert_eq!(code, Some(130));
+    assert!(elapsed < std::time::Duration::from_secs(8));
+    assert!(stderr.contains("Interrupted by SIGINT"), "{}", stderr);
+}
+
+#[test]
+fn test_exit_status_policy() {
+    let temp_dir = tempdir().unwrap();
+    for (name, code) in [("alpha", 3), ("beta", 0), ("gamma", 5)] {
+        fs::create_dir(temp_dir.path().join(name)).unwrap();
+        fs::write(temp_dir.path().join(name).join("code"), code.to_string()).unwrap();
+    }
+
+    for (policy, expected) in [("first", 3), ("last", 5), ("max", 5), ("count", 2)] {
+        loop_command()
+            .current_dir(temp_dir.path())
+            .args(["--no-rc", "--exit-status", policy, "exit $(cat code)"])
+            .assert()
+            .code(expected);
+    }
+
+    fs::write(
+        temp_dir.path().join(".looprc"),
+        r#"{"exit_status": "count"}"#,
+    )
+    .unwrap();
+    loop_command()
+        .current_dir(temp_dir.path())
+        .args(["--no-rc", "exit $(cat code)"])
+        .assert()
+        .code(2);
+}
+
+#[test]
+fn test_included_child_runs_once() {
+    let temp_dir = tempdir().unwrap();
+    fs::create_dir(temp_dir.path().join("alpha")).unwrap();
+
+    let output = loop_command()
+        .current_dir(temp_dir.path())
+        .env("XDG_CACHE_HOME", temp_dir.path().join("cache"))
+        .args([
+            "--no-rc",
+            "--include",
+            "alpha",
+            "./alpha/",
+            "--",
+            "echo ran",
+        ])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(stdout.matches("ran").count(), 1);
+}
+
+#[test]
+fn test_sort_order() {
+    let temp_dir = tempdir().unwrap();
+    let root = temp_dir.path().join("root");
+    for (name, delay) in [("alpha", "0.4"), ("beta", "0"), ("gamma", "0.2")] {
+        fs::create_dir_all(root.join(name)).unwrap();
+        fs::write(root.join(name).join("delay"), delay).unwrap();
+    }
+    let order = |args: &[&str]| -> Vec<String> {
+        let output = loop_command()
+            .current_dir(&root)
+            .env("XDG_CACHE_HOME", temp_dir.path().join("cache"))
+            .arg("--no-rc")
+            .args(args)
+            .arg("sleep $(cat delay)")
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        String::from_utf8(output.stdout)
+            .unwrap()
+            .lines()
+            .filter_map(|line| line.strip_suffix(" ✓\x1b[0m"))
+            .map(|line| line.trim_start_matches("\x1b[32m").to_string())
+            .collect()
+    };
+
+    assert_eq!(order(&["--reverse"]), vec!["gamma", "beta", "alpha"]);
+    assert_eq!(
+        order(&["--sort", "name", "--reverse"]),
+        vec!["gamma", "beta", "alpha"]
+    );
+    // Durations are only recorded by runs sorted by them; the first has none to use
+    assert!(!temp_dir.path().join("cache").exists());
+    assert_eq!(
+        order(&["--sort", "duration"]),
+        vec!["alpha", "beta", "gamma"]
+    );
+    assert_eq!(
+        order(&["--sort", "duration"]),
+        vec!["beta", "gamma", "alpha"]
+    );
+    assert_eq!(
+        order(&["--sort", "duration", "--reverse"]),
+        vec!["alpha", "gamma", "beta"]
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_command_killed_by_signal() {
+    let temp_dir = tempdir().unwrap();
+    fs::create_dir(temp_dir.path().join("alpha")).unwrap();
+
+    loop_command()
+        .current_dir(temp_dir.path())
+        .args(["--no-rc", "--shell", "sh", "kill -9 $$"])
+        .assert()
+        .code(137)
+        .stdout(predicate::str::contains("alpha ✗: killed by SIGKILL"));
+}
+
+#[test]
+fn test_invalid_pattern_is_a_usage_error() {
+    let temp_dir = tempdir().unwrap();
+    fs::create_dir(temp_dir.path().join("alpha")).unwrap();
+
+    loop_command()
+        .current_dir(temp_dir.path())
+        .args(["--include-pattern", "(unclosed", "ls"])
+        .assert()
+        .code(exitcode::USAGE)
+        .stderr(predicate::str::contains(
+            "Invalid --include-pattern pattern",
+        ));
+}
+
 #[cfg(test)]
 mod tests {
-    use super::*;
-    use loop_lib::config::{create_looprc, read_looprc, LoopConfig};
+    use loop_lib::config::{init_config, load_config, ConfigFormat, LoopConfig};
 
     use std::fs;
     use tempfile::tempdir;
 
     #[test]
-    fn test_create_looprc() {
-        let _lock = CURRENT_DIR_MUTEX.lock().unwrap();
+    fn test_init_config() {
         let temp_dir = tempdir().unwrap();
-        std::env::set_current_dir(&temp_dir).unwrap();
-
-        create_looprc();
 
-        let content = std::fs::read_to_string(".looprc").unwrap();
-        println!("Created .looprc content:\n{}", content);
+        let path = init_config(temp_dir.path(), ConfigFormat::Json, false).unwrap();
+        assert_eq!(path, temp_dir.path().join(".looprc"));
 
+        let content = fs::read_to_string(&path).unwrap();
         let config: LoopConfig = serde_json::from_str(&content).unwrap();
 
         assert!(!config.ignore.is_empty());
@@ -96,28 +941,39 @@ mod tests {
     }
 
     #[test]
-    fn test_read_looprc() {
-        let _lock = CURRENT_DIR_MUTEX.lock().unwrap();
+    fn test_load_config() {
         let temp_dir = tempdir().unwrap();
-        std::env::set_current_dir(&temp_dir).unwrap();
 
         let test_config = LoopConfig {
             ignore: vec!["test_dir".to_string()],
+            ..LoopConfig::default()
         };
         let json = serde_json::to_string_pretty(&test_config).unwrap();
-        fs::write(".looprc", json).unwrap();
+        fs::write(temp_dir.path().join(".looprc"), json).unwrap();
 
-        let read_config = read_looprc();
+        let read_config = load_config(temp_dir.path()).unwrap();
         assert_eq!(read_config.ignore, vec!["test_dir".to_string()]);
     }
 
     #[test]
-    fn test_read_looprc_missing_file() {
-        let _lock = CURRENT_DIR_MUTEX.lock().unwrap();
+    fn test_load_config_missing_file() {
         let temp_dir = tempdir().unwrap();
-        std::env::set_current_dir(&temp_dir).unwrap();
 
-        let config = read_looprc();
+        let config = load_config(temp_dir.path()).unwrap();
         assert!(config.ignore.is_empty());
     }
+
+    #[test]
+    fn test_load_config_invalid_file() {
+        let temp_dir = tempdir().unwrap();
+        fs::write(temp_dir.path().join(".looprc"), r#"{"ignroe": []}"#).unwrap();
+
+        let err = loop_lib::LoopError::from(load_config(temp_dir.path()).unwrap_err());
+        assert_eq!(err.exit_code(), exitcode::CONFIG);
+
+        let err = loop_lib::LoopError::from(
+            init_config(temp_dir.path(), ConfigFormat::Json, false).unwrap_err(),
+        );
+        assert_eq!(err.exit_code(), exitcode::CANTCREAT);
+    }
 }
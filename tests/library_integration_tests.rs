@@ -1,18 +1,25 @@
 use loop_lib::args::LoopOptions;
+use loop_lib::executor::{ExecSettings, Executor, Iteration, Outcome};
+use loop_lib::observer::{Event, NoopObserver, Observer};
+use loop_lib::output::OutputMode;
+use loop_lib::report::{SkipReason, Status};
+use loop_lib::source::{DirectoryList, DirectorySource, GitRepositories, Recursive};
+use loop_lib::LoopError;
+use std::fs;
+use std::path::Path;
+use std::sync::Mutex;
 
 #[test]
 fn test_loop_options_creation() {
-    let options = LoopOptions {
-        command: vec!["echo".to_string(), "test".to_string()],
-        cwd: Some("/home/user".to_string()),
-        include: Some(vec!["dir1".to_string(), "dir2".to_string()]),
-        exclude: Some(vec!["dir3".to_string()]),
-        include_only: None,
-        exclude_only: None,
-        include_pattern: Some("*.rs".to_string()),
-        exclude_pattern: Some("*.tmp".to_string()),
-        init: false,
-    };
+    let options = LoopOptions::builder()
+        .command(["echo", "test"])
+        .cwd("/home/user")
+        .include(["dir1", "dir2"])
+        .exclude(["dir3"])
+        .include_pattern("*.rs")
+        .exclude_pattern("*.tmp")
+        .build()
+        .unwrap();
 
     assert_eq!(options.command, vec!["echo", "test"]);
     assert_eq!(options.cwd, Some("/home/user".to_string()));
@@ -28,17 +35,15 @@ fn test_loop_options_creation() {
 
 #[test]
 fn test_loop_options_mixed() {
-    let options = LoopOptions {
-        command: vec!["npm".to_string(), "run".to_string(), "test".to_string()],
-        cwd: Some(".".to_string()),
-        include: Some(vec!["src".to_string()]),
-        exclude: None,
-        include_only: None,
-        exclude_only: Some(vec!["node_modules".to_string()]),
-        include_pattern: Some("*.js".to_string()),
-        exclude_pattern: None,
-        init: true,
-    };
+    let options = LoopOptions::builder()
+        .command(["npm", "run", "test"])
+        .cwd(".")
+        .include(["src"])
+        .exclude_only(["node_modules"])
+        .include_pattern("*.js")
+        .init(true)
+        .build()
+        .unwrap();
 
     assert_eq!(options.command, vec!["npm", "run", "test"]);
     assert_eq!(options.cwd, Some(".".to_string()));
@@ -53,17 +58,7 @@ fn test_loop_options_mixed() {
 
 #[test]
 fn test_loop_options_empty_command() {
-    let options = LoopOptions {
-        command: vec![],
-        cwd: None,
-        include: None,
-        exclude: None,
-        include_only: None,
-        exclude_only: None,
-        include_pattern: None,
-        exclude_pattern: None,
-        init: false,
-    };
+    let options = LoopOptions::default();
 
     assert!(options.command.is_empty());
     assert!(options.cwd.is_none());
@@ -74,21 +69,19 @@ fn test_loop_options_empty_command() {
     assert!(options.include_pattern.is_none());
     assert!(options.exclude_pattern.is_none());
     assert!(!options.init);
+
+    let err = LoopOptions::builder().build().unwrap_err();
+    assert!(matches!(err, LoopError::InvalidOptions(_)));
 }
 
 #[test]
 fn test_loop_options_only_patterns() {
-    let options = LoopOptions {
-        command: vec!["grep".to_string(), "-r".to_string(), "TODO".to_string()],
-        cwd: None,
-        include: None,
-        exclude: None,
-        include_only: None,
-        exclude_only: None,
-        include_pattern: Some("*.{rs,toml}".to_string()),
-        exclude_pattern: Some("**/target/**".to_string()),
-        init: false,
-    };
+    let options = LoopOptions::builder()
+        .command(["grep", "-r", "TODO"])
+        .include_pattern("*.{rs,toml}")
+        .exclude_pattern("**/target/**")
+        .build()
+        .unwrap();
 
     assert_eq!(options.command, vec!["grep", "-r", "TODO"]);
     assert!(options.cwd.is_none());
@@ -100,3 +93,272 @@ fn test_loop_options_only_patterns() {
     assert_eq!(options.exclude_pattern, Some("**/target/**".to_string()));
     assert!(!options.init);
 }
+
+#[test]
+fn test_loop_options_builder_conflicts() {
+    let err = LoopOptions::builder()
+        .command(["ls"])
+        .shell("bash")
+        .no_shell(true)
+        .build()
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Invalid options: shell cannot be used with no_shell"
+    );
+}
+
+/// Records the commands it is asked to run, failing in directories named `broken`.
+#[derive(Default)]
+struct RecordingExecutor {
+    calls: Mutex<Vec<(String, Vec<String>, Iteration)>>,
+}
+
+impl Executor for RecordingExecutor {
+    fn execute(
+        &self,
+        dir: &Path,
+        command: &[String],
+        _settings: &ExecSettings,
+        iteration: Iteration,
+        _observer: &dyn Observer,
+    ) -> Result<Outcome, LoopError> {
+        let name = dir.file_name().unwrap().to_string_lossy().into_owned();
+        let outcome = if name == "broken" {
+            Outcome::Exited(2)
+        } else {
+            Outcome::Exited(0)
+        };
+        self.calls
+            .lock()
+            .unwrap()
+            .push((name, command.to_vec(), iteration));
+        Ok(outcome)
+    }
+}
+
+#[test]
+fn test_execute_loop_with_custom_executor() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    for dir in ["alpha", "broken", "gamma"] {
+        fs::create_dir(temp_dir.path().join(dir)).unwrap();
+    }
+
+    let options = LoopOptions::builder()
+        .command(["make", "test"])
+        .cwd(temp_dir.path().to_string_lossy())
+        .exclude(["gamma"])
+        .build()
+        .unwrap();
+    let executor = RecordingExecutor::default();
+    let report = loop_lib::execute_loop_with(options, &executor, &NoopObserver).unwrap();
+
+    let calls = executor.calls.into_inner().unwrap();
+    let command = vec!["make".to_string(), "test".to_string()];
+    assert_eq!(
+        calls,
+        vec![
+            (
+                "alpha".to_string(),
+                command.clone(),
+                Iteration { index: 1, total: 2 }
+            ),
+            (
+                "broken".to_string(),
+                command,
+                Iteration { index: 2, total: 2 }
+            ),
+        ]
+    );
+    assert_eq!(report.failed().count(), 1);
+    assert_eq!(report.directories[2].status, Status::Skipped);
+    assert_eq!(report.exit_code(), 2);
+}
+
+#[test]
+fn test_pipeline_with_custom_executor() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    fs::create_dir(temp_dir.path().join("broken")).unwrap();
+
+    let options = LoopOptions::builder()
+        .cwd(temp_dir.path().to_string_lossy())
+        .steps(["-lint", "build", "test"])
+        .build()
+        .unwrap();
+    let executor = RecordingExecutor::default();
+    let report = loop_lib::execute_loop_with(options, &executor, &NoopObserver).unwrap();
+
+    let steps: Vec<_> = report.directories[0]
+        .steps
+        .iter()
+        .map(|step| (step.label.as_str(), step.status))
+        .collect();
+    assert_eq!(
+        steps,
+        vec![
+            ("lint", Status::Failed),
+            ("build", Status::Failed),
+            ("test", Status::Skipped),
+        ]
+    );
+    assert_eq!(executor.calls.lock().unwrap().len(), 2);
+}
+
+#[test]
+fn test_execute_loop_with_custom_source() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    for dir in ["apps/web/.git", "libs/core/.git", "libs/vendor", "tools"] {
+        fs::create_dir_all(temp_dir.path().join(dir)).unwrap();
+    }
+
+    let sources: Vec<Box<dyn DirectorySource>> = vec![
+        Box::new(GitRepositories::default()),
+        Box::new(DirectoryList(vec!["tools".into(), "missing".into()])),
+    ];
+    let options = LoopOptions::builder()
+        .command(["make"])
+        .cwd(temp_dir.path().to_string_lossy())
+        .exclude(["core"])
+        .source(sources)
+        .build()
+        .unwrap();
+    let executor = RecordingExecutor::default();
+    let report = loop_lib::execute_loop_with(options, &executor, &NoopObserver).unwrap();
+
+    let ran: Vec<_> = executor
+        .calls
+        .into_inner()
+        .unwrap()
+        .into_iter()
+        .map(|(name, ..)| name)
+        .collect();
+    assert_eq!(ran, vec!["web", "tools"]);
+    let skipped: Vec<_> = report
+        .skipped()
+        .map(|dir| dir.skip_reason.unwrap())
+        .collect();
+    assert_eq!(
+        skipped,
+        vec![SkipReason::Excluded, SkipReason::NotADirectory]
+    );
+}
+
+#[test]
+fn test_recursive_source_skips_excluded_trees() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    for dir in ["web/node_modules/pkg", "web/src", "target/debug"] {
+        fs::create_dir_all(temp_dir.path().join(dir)).unwrap();
+    }
+    fs::write(temp_dir.path().join(".looprc"), r#"{"ignore": ["target"]}"#).unwrap();
+
+    let options = LoopOptions::builder()
+        .command(["make"])
+        .cwd(temp_dir.path().to_string_lossy())
+        .exclude(["node_modules"])
+        .source(Recursive::default())
+        .build()
+        .unwrap();
+    let executor = RecordingExecutor::default();
+    let report = loop_lib::execute_loop_with(options, &executor, &NoopObserver).unwrap();
+
+    let considered: Vec<_> = report
+        .directories
+        .iter()
+        .map(|dir| {
+            dir.path
+                .strip_prefix(temp_dir.path())
+                .unwrap()
+                .to_path_buf()
+        })
+        .collect();
+    assert_eq!(
+        considered,
+        ["target", "web", "web/node_modules", "web/src"].map(std::path::PathBuf::from)
+    );
+    assert_eq!(report.ran().count(), 2);
+}
+
+#[test]
+fn test_observer_receives_events_in_order() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    for dir in ["alpha", "skipped"] {
+        fs::create_dir(temp_dir.path().join(dir)).unwrap();
+    }
+
+    let options = LoopOptions::builder()
+        .cwd(temp_dir.path().to_string_lossy())
+        .steps(["build", "test"])
+        .exclude(["skipped"])
+        .build()
+        .unwrap();
+    let events = Mutex::new(Vec::new());
+    let observer = |event: &Event<'_>| {
+        let name = match event {
+            Event::RunStarted { .. } => "run started".to_string(),
+            Event::DirectoryDiscovered { path } => {
+                format!("discovered {}", path.file_name().unwrap().to_string_lossy())
+            }
+            Event::DirectorySkipped { path, reason } => format!(
+                "skipped {} ({})",
+                path.file_name().unwrap().to_string_lossy(),
+                reason
+            ),
+            Event::DirectoryStarted { iteration, .. } => {
+                format!("started {} of {}", iteration.index, iteration.total)
+            }
+            Event::StepStarted { label, .. } => format!("step {}", label),
+            Event::StepFinished { step, .. } => format!("step {} {:?}", step.label, step.status),
+            Event::OutputLine { line, .. } => format!("output {}", line),
+            Event::DirectoryFinished { outcome } => format!("finished {:?}", outcome.status),
+            Event::RunFinished { report } => format!("run finished {}", report.exit_code()),
+        };
+        events.lock().unwrap().push(name);
+    };
+    loop_lib::execute_loop_with(options, &RecordingExecutor::default(), &observer).unwrap();
+
+    assert_eq!(
+        events.into_inner().unwrap(),
+        vec![
+            "run started",
+            "discovered alpha",
+            "discovered skipped",
+            "started 1 of 1",
+            "step build",
+            "step build Succeeded",
+            "step test",
+            "step test Succeeded",
+            "finished Succeeded",
+            "skipped skipped (excluded)",
+            "run finished 0",
+        ]
+    );
+}
+
+#[test]
+fn test_execute_loop_captures_output() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    for dir in ["alpha", "beta"] {
+        fs::create_dir(temp_dir.path().join(dir)).unwrap();
+    }
+
+    let options = LoopOptions::builder()
+        .command(["echo {name}; echo {index} >&2"])
+        .cwd(temp_dir.path().to_string_lossy())
+        .no_rc(true)
+        .output(OutputMode::Capture { limit: 3 })
+        .build()
+        .unwrap();
+    let report =
+        loop_lib::execute_loop_with(options, &loop_lib::executor::ShellExecutor, &NoopObserver)
+            .unwrap();
+
+    let outputs: Vec<_> = report
+        .directories
+        .iter()
+        .map(|dir| dir.output.clone().unwrap())
+        .collect();
+    assert_eq!(outputs[0].stdout, b"alp");
+    assert!(outputs[0].truncated);
+    assert_eq!(outputs[1].stderr, b"2\n");
+    assert_eq!(outputs[1].stdout, b"bet");
+}